@@ -0,0 +1,474 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, BTreeMap};
+use fnv::FnvHashMap;
+
+use super::{Database, DocumentId};
+use super::data_dictionary::FieldId;
+use super::date::{self, DateUnit};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeBucket {
+    pub key: Option<String>,
+    // Inclusive
+    pub from: Option<f64>,
+    // Exclusive
+    pub to: Option<f64>,
+}
+
+// Aggregations that produce buckets can have other aggregations nested inside them,
+// these are run on the documents in each bucket
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregation {
+    // Counts the documents with each value of a keyword field and returns the most common ones
+    Terms {
+        field: FieldId,
+        size: usize,
+        aggregations: HashMap<String, Aggregation>,
+    },
+
+    // Groups the values of a numeric field into fixed size buckets
+    Histogram {
+        field: FieldId,
+        interval: f64,
+        aggregations: HashMap<String, Aggregation>,
+    },
+
+    // Groups the values of a date field into calendar buckets
+    // The offset (in milliseconds) is of the timezone that the buckets start and end in
+    DateHistogram {
+        field: FieldId,
+        interval: DateUnit,
+        offset: i64,
+        aggregations: HashMap<String, Aggregation>,
+    },
+
+    Range {
+        field: FieldId,
+        ranges: Vec<RangeBucket>,
+        aggregations: HashMap<String, Aggregation>,
+    },
+
+    Stats {
+        field: FieldId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+#[serde(untagged)]
+pub enum BucketKey {
+    Text(String),
+    Number(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct Bucket {
+    pub key: BucketKey,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_as_string: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<f64>,
+    pub doc_count: usize,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub aggregations: HashMap<String, AggregationResult>,
+}
+
+impl Bucket {
+    fn new(key: BucketKey, document_ids: &[DocumentId], aggregations: &HashMap<String, Aggregation>, db: &Database) -> Bucket {
+        Bucket {
+            key,
+            key_as_string: None,
+            from: None,
+            to: None,
+            doc_count: document_ids.len(),
+            aggregations: run_all(aggregations, db, document_ids),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+#[serde(untagged)]
+pub enum AggregationResult {
+    Terms {
+        buckets: Vec<Bucket>,
+        // The number of documents with values that didn't make it into the top buckets
+        other_doc_count: usize,
+    },
+    Buckets {
+        buckets: Vec<Bucket>,
+    },
+    Stats {
+        count: usize,
+        min: Option<f64>,
+        max: Option<f64>,
+        avg: Option<f64>,
+        sum: f64,
+    },
+}
+
+// Runs each aggregation over the same set of documents
+pub fn run_all(aggregations: &HashMap<String, Aggregation>, db: &Database, document_ids: &[DocumentId]) -> HashMap<String, AggregationResult> {
+    aggregations.iter().map(|(name, aggregation)| (name.clone(), aggregation.run(db, document_ids))).collect()
+}
+
+// Finds the value of a numeric field for each document that has one
+fn numeric_values<'a>(db: &'a Database, field: FieldId, document_ids: &'a [DocumentId]) -> impl Iterator<Item = (DocumentId, f64)> + 'a {
+    let field = db.numeric_fields.get(&field);
+    document_ids.iter().filter_map(move |document_id| field.and_then(|field| field.doc_value(*document_id)).map(|value| (*document_id, value.as_f64())))
+}
+
+impl Aggregation {
+    // Runs the aggregation over a set of matched documents
+    pub fn run(&self, db: &Database, document_ids: &[DocumentId]) -> AggregationResult {
+        match self {
+            Aggregation::Terms { field, size, aggregations } => {
+                let mut buckets: FnvHashMap<&str, Vec<DocumentId>> = FnvHashMap::default();
+
+                if let Some(field) = db.keyword_fields.get(field) {
+                    for document_id in document_ids {
+                        for term_id in field.doc_values(*document_id) {
+                            if let Some(term) = db.term_dictionary.term_ids.get(term_id) {
+                                buckets.entry(term.as_str()).or_default().push(*document_id);
+                            }
+                        }
+                    }
+                }
+
+                let mut buckets = buckets.into_iter().collect::<Vec<_>>();
+                buckets.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(b.0)));
+
+                let other_doc_count = buckets.iter().skip(*size).map(|(_, document_ids)| document_ids.len()).sum();
+                let buckets = buckets.into_iter().take(*size).map(|(key, document_ids)| {
+                    Bucket::new(BucketKey::Text(key.to_owned()), &document_ids, aggregations, db)
+                }).collect();
+
+                AggregationResult::Terms { buckets, other_doc_count }
+            }
+            Aggregation::Histogram { field, interval, aggregations } => {
+                // Only buckets that contain documents are returned
+                let mut buckets: BTreeMap<i64, Vec<DocumentId>> = BTreeMap::new();
+
+                for (document_id, value) in numeric_values(db, *field, document_ids) {
+                    buckets.entry((value / interval).floor() as i64).or_default().push(document_id);
+                }
+
+                AggregationResult::Buckets {
+                    buckets: buckets.into_iter().map(|(index, document_ids)| {
+                        Bucket::new(BucketKey::Number(index as f64 * interval), &document_ids, aggregations, db)
+                    }).collect(),
+                }
+            }
+            Aggregation::DateHistogram { field, interval, offset, aggregations } => {
+                let mut buckets: BTreeMap<i64, Vec<DocumentId>> = BTreeMap::new();
+
+                for (document_id, value) in numeric_values(db, *field, document_ids) {
+                    // Round in local time, then convert the start of the bucket back to UTC
+                    // Dates that are too far from 1970 for their bucket to be represented are left out
+                    let key = (value as i64).checked_add(*offset).and_then(|local| date::round_down(local, *interval)).and_then(|key| key.checked_sub(*offset));
+                    if let Some(key) = key {
+                        buckets.entry(key).or_default().push(document_id);
+                    }
+                }
+
+                AggregationResult::Buckets {
+                    buckets: buckets.into_iter().map(|(key, document_ids)| {
+                        let mut bucket = Bucket::new(BucketKey::Number(key as f64), &document_ids, aggregations, db);
+                        bucket.key_as_string = Some(date::format_datetime(key, *offset));
+                        bucket
+                    }).collect(),
+                }
+            }
+            Aggregation::Range { field, ranges, aggregations } => {
+                let values = numeric_values(db, *field, document_ids).collect::<Vec<_>>();
+
+                AggregationResult::Buckets {
+                    buckets: ranges.iter().map(|range| {
+                        let document_ids = values.iter().filter(|(_, value)| {
+                            range.from.map(|from| *value >= from).unwrap_or(true) && range.to.map(|to| *value < to).unwrap_or(true)
+                        }).map(|(document_id, _)| *document_id).collect::<Vec<_>>();
+
+                        let key = range.key.clone().unwrap_or_else(|| {
+                            let format_bound = |bound: Option<f64>| bound.map(|bound| bound.to_string()).unwrap_or_else(|| "*".to_owned());
+                            format!("{}-{}", format_bound(range.from), format_bound(range.to))
+                        });
+
+                        let mut bucket = Bucket::new(BucketKey::Text(key), &document_ids, aggregations, db);
+                        bucket.from = range.from;
+                        bucket.to = range.to;
+                        bucket
+                    }).collect(),
+                }
+            }
+            Aggregation::Stats { field } => {
+                let mut count = 0;
+                let mut min: Option<f64> = None;
+                let mut max: Option<f64> = None;
+                let mut sum = 0.0;
+
+                for (_, value) in numeric_values(db, *field, document_ids) {
+                    count += 1;
+                    sum += value;
+                    min = Some(min.map_or(value, |min| min.min(value)));
+                    max = Some(max.map_or(value, |max| max.max(value)));
+                }
+
+                let avg = if count > 0 { Some(sum / count as f64) } else { None };
+
+                AggregationResult::Stats { count, min, max, avg, sum }
+            }
+        }
+    }
+}
+
+// Merges the results of the same aggregations that were run on several databases
+// Aggregations that are missing from one of the results (because the field doesn't exist there) are taken from the other
+pub fn merge_all(aggregations: &HashMap<String, Aggregation>, mut a: HashMap<String, AggregationResult>, b: HashMap<String, AggregationResult>) -> HashMap<String, AggregationResult> {
+    for (name, b_result) in b {
+        let result = match (a.remove(&name), aggregations.get(&name)) {
+            (Some(a_result), Some(aggregation)) => a_result.merge(b_result, aggregation),
+            (Some(a_result), None) => a_result,
+            (None, _) => b_result,
+        };
+
+        a.insert(name, result);
+    }
+
+    a
+}
+
+// Combines buckets with the same key. Buckets that are only in b are added after the ones in a
+fn merge_buckets(a: Vec<Bucket>, b: Vec<Bucket>, aggregations: &HashMap<String, Aggregation>) -> Vec<Bucket> {
+    let mut buckets = a;
+
+    for b_bucket in b {
+        match buckets.iter_mut().find(|bucket| bucket.key == b_bucket.key) {
+            Some(bucket) => {
+                bucket.doc_count += b_bucket.doc_count;
+                bucket.aggregations = merge_all(aggregations, std::mem::take(&mut bucket.aggregations), b_bucket.aggregations);
+            }
+            None => buckets.push(b_bucket),
+        }
+    }
+
+    buckets
+}
+
+impl Aggregation {
+    // The aggregations that are run inside each bucket
+    fn sub_aggregations(&self) -> Option<&HashMap<String, Aggregation>> {
+        match self {
+            Aggregation::Terms { aggregations, .. } | Aggregation::Histogram { aggregations, .. } | Aggregation::DateHistogram { aggregations, .. } | Aggregation::Range { aggregations, .. } => Some(aggregations),
+            Aggregation::Stats { .. } => None,
+        }
+    }
+}
+
+impl AggregationResult {
+    // Merges the results of running the aggregation on two databases
+    // Note: terms buckets are only counted in the databases where they made it into the top buckets, so the counts are approximate
+    pub fn merge(self, other: AggregationResult, aggregation: &Aggregation) -> AggregationResult {
+        let no_aggregations = HashMap::new();
+        let sub_aggregations = aggregation.sub_aggregations().unwrap_or(&no_aggregations);
+
+        match (self, other) {
+            (AggregationResult::Terms { buckets: a_buckets, other_doc_count: a_other_doc_count }, AggregationResult::Terms { buckets: b_buckets, other_doc_count: b_other_doc_count }) => {
+                let size = match aggregation {
+                    Aggregation::Terms { size, .. } => *size,
+                    _ => a_buckets.len().max(b_buckets.len()),
+                };
+                let mut buckets = merge_buckets(a_buckets, b_buckets, sub_aggregations);
+                buckets.sort_by(|a, b| b.doc_count.cmp(&a.doc_count).then_with(|| match (&a.key, &b.key) {
+                    (BucketKey::Text(a), BucketKey::Text(b)) => a.cmp(b),
+                    _ => Ordering::Equal,
+                }));
+
+                let other_doc_count = a_other_doc_count + b_other_doc_count + buckets.iter().skip(size).map(|bucket| bucket.doc_count).sum::<usize>();
+                buckets.truncate(size);
+
+                AggregationResult::Terms { buckets, other_doc_count }
+            }
+            (AggregationResult::Buckets { buckets: a_buckets }, AggregationResult::Buckets { buckets: b_buckets }) => {
+                let mut buckets = merge_buckets(a_buckets, b_buckets, sub_aggregations);
+
+                // Histogram buckets are in order of their key. Range buckets keep the order they were given in
+                if buckets.iter().all(|bucket| match bucket.key { BucketKey::Number(_) => true, BucketKey::Text(_) => false }) {
+                    buckets.sort_by(|a, b| match (&a.key, &b.key) {
+                        (BucketKey::Number(a), BucketKey::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+                        _ => Ordering::Equal,
+                    });
+                }
+
+                AggregationResult::Buckets { buckets }
+            }
+            (AggregationResult::Stats { count: a_count, min: a_min, max: a_max, sum: a_sum, .. }, AggregationResult::Stats { count: b_count, min: b_min, max: b_max, sum: b_sum, .. }) => {
+                let count = a_count + b_count;
+                let sum = a_sum + b_sum;
+                let min = match (a_min, b_min) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                let max = match (a_max, b_max) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                };
+                let avg = if count > 0 { Some(sum / count as f64) } else { None };
+
+                AggregationResult::Stats { count, min, max, avg, sum }
+            }
+            // The same aggregation always produces the same kind of result, so this only happens if the field has a different type in each database
+            (a, _) => a,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{Database, Document, DocumentId};
+    use crate::data_dictionary::FieldId;
+    use crate::date::{self, DateUnit};
+    use crate::numeric_index::NumericValue;
+    use super::{Aggregation, AggregationResult, BucketKey, RangeBucket, run_all, merge_all};
+
+    fn make_database(values: &[f64]) -> (Database, Vec<DocumentId>) {
+        let mut db = Database::default();
+        let document_ids = values.iter().enumerate().map(|(i, value)| {
+            let mut doc = Document::default();
+            doc.numeric_fields.insert(FieldId(0), NumericValue::Float(*value));
+            db.insert_document(i.to_string(), doc)
+        }).collect();
+
+        (db, document_ids)
+    }
+
+    fn bucket_counts(result: &AggregationResult) -> Vec<(BucketKey, usize)> {
+        match result {
+            AggregationResult::Buckets { buckets } => buckets.iter().map(|bucket| (bucket.key.clone(), bucket.doc_count)).collect(),
+            _ => panic!("expected buckets"),
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let (db, document_ids) = make_database(&[1.0, 4.0, 5.0, 23.0, -2.0]);
+        let aggregation = Aggregation::Histogram { field: FieldId(0), interval: 5.0, aggregations: HashMap::new() };
+
+        assert_eq!(bucket_counts(&aggregation.run(&db, &document_ids)), vec![
+            (BucketKey::Number(-5.0), 1),
+            (BucketKey::Number(0.0), 2),
+            (BucketKey::Number(5.0), 1),
+            (BucketKey::Number(20.0), 1),
+        ]);
+    }
+
+    #[test]
+    fn test_date_histogram() {
+        // 2020-05-17T23:30:00Z and 2020-05-18T00:30:00Z
+        let (db, document_ids) = make_database(&[1589758200000.0, 1589761800000.0]);
+
+        let aggregation = Aggregation::DateHistogram { field: FieldId(0), interval: DateUnit::Day, offset: 0, aggregations: HashMap::new() };
+        assert_eq!(bucket_counts(&aggregation.run(&db, &document_ids)), vec![
+            (BucketKey::Number(1589673600000.0), 1),
+            (BucketKey::Number(1589760000000.0), 1),
+        ]);
+
+        // Both are on the 18th in UTC+01:00
+        let aggregation = Aggregation::DateHistogram { field: FieldId(0), interval: DateUnit::Day, offset: 3600000, aggregations: HashMap::new() };
+        let result = aggregation.run(&db, &document_ids);
+        assert_eq!(bucket_counts(&result), vec![(BucketKey::Number(1589756400000.0), 2)]);
+        match result {
+            AggregationResult::Buckets { buckets } => assert_eq!(buckets[0].key_as_string, Some("2020-05-18T00:00:00.000+01:00".to_owned())),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_date_histogram_out_of_range() {
+        // The buckets of dates this far from 1970 might not be representable, they must be left out rather than overflow
+        let (db, document_ids) = make_database(&[i64::MAX as f64, i64::MIN as f64, 1589758200000.0]);
+
+        for interval in &[DateUnit::Year, DateUnit::Month, DateUnit::Week, DateUnit::Second] {
+            let aggregation = Aggregation::DateHistogram { field: FieldId(0), interval: *interval, offset: 3600000, aggregations: HashMap::new() };
+            let key = date::round_down(1589758200000 + 3600000, *interval).unwrap() - 3600000;
+            assert!(bucket_counts(&aggregation.run(&db, &document_ids)).contains(&(BucketKey::Number(key as f64), 1)));
+        }
+    }
+
+    #[test]
+    fn test_range_with_nested_stats() {
+        let (db, document_ids) = make_database(&[1.0, 4.0, 5.0, 23.0]);
+        let mut aggregations = HashMap::new();
+        aggregations.insert("stats".to_owned(), Aggregation::Stats { field: FieldId(0) });
+        let aggregation = Aggregation::Range {
+            field: FieldId(0),
+            ranges: vec![
+                RangeBucket { key: None, from: None, to: Some(5.0) },
+                RangeBucket { key: Some("high".to_owned()), from: Some(5.0), to: None },
+            ],
+            aggregations,
+        };
+
+        let result = aggregation.run(&db, &document_ids);
+        assert_eq!(bucket_counts(&result), vec![(BucketKey::Text("*-5".to_owned()), 2), (BucketKey::Text("high".to_owned()), 2)]);
+        match result {
+            AggregationResult::Buckets { buckets } => {
+                assert_eq!(buckets[1].aggregations["stats"], AggregationResult::Stats { count: 2, min: Some(5.0), max: Some(23.0), avg: Some(14.0), sum: 28.0 });
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let (a_db, a_document_ids) = make_database(&[1.0, 2.0, 11.0]);
+        let (b_db, b_document_ids) = make_database(&[3.0, 25.0]);
+
+        let mut aggregations = HashMap::new();
+        aggregations.insert("histogram".to_owned(), Aggregation::Histogram { field: FieldId(0), interval: 10.0, aggregations: HashMap::new() });
+        aggregations.insert("stats".to_owned(), Aggregation::Stats { field: FieldId(0) });
+
+        let result = merge_all(&aggregations, run_all(&aggregations, &a_db, &a_document_ids), run_all(&aggregations, &b_db, &b_document_ids));
+        assert_eq!(bucket_counts(&result["histogram"]), vec![(BucketKey::Number(0.0), 3), (BucketKey::Number(10.0), 1), (BucketKey::Number(20.0), 1)]);
+        assert_eq!(result["stats"], AggregationResult::Stats { count: 5, min: Some(1.0), max: Some(25.0), avg: Some(8.4), sum: 42.0 });
+    }
+
+    #[test]
+    fn test_merge_terms() {
+        let make_terms_database = |values: &[&str]| {
+            let mut db = Database::default();
+            let document_ids = values.iter().enumerate().map(|(i, value)| {
+                let mut doc = Document::default();
+                doc.keyword_fields.insert(FieldId(0), vec![db.term_dictionary.get_or_insert(value)]);
+                db.insert_document(i.to_string(), doc)
+            }).collect::<Vec<_>>();
+
+            (db, document_ids)
+        };
+        let (a_db, a_document_ids) = make_terms_database(&["x", "x", "y", "z"]);
+        let (b_db, b_document_ids) = make_terms_database(&["p", "q", "r", "r", "r"]);
+
+        let terms_counts = |size| {
+            let mut aggregations = HashMap::new();
+            aggregations.insert("terms".to_owned(), Aggregation::Terms { field: FieldId(0), size, aggregations: HashMap::new() });
+
+            match merge_all(&aggregations, run_all(&aggregations, &a_db, &a_document_ids), run_all(&aggregations, &b_db, &b_document_ids)).remove("terms") {
+                Some(AggregationResult::Terms { buckets, other_doc_count }) => (buckets.into_iter().map(|bucket| (bucket.key, bucket.doc_count)).collect::<Vec<_>>(), other_doc_count),
+                _ => panic!("expected terms"),
+            }
+        };
+
+        // All of the buckets fit, even though there are more of them than either database returned
+        assert_eq!(terms_counts(10), (vec![
+            (BucketKey::Text("r".to_owned()), 3),
+            (BucketKey::Text("x".to_owned()), 2),
+            (BucketKey::Text("p".to_owned()), 1),
+            (BucketKey::Text("q".to_owned()), 1),
+            (BucketKey::Text("y".to_owned()), 1),
+            (BucketKey::Text("z".to_owned()), 1),
+        ], 0));
+
+        assert_eq!(terms_counts(2), (vec![(BucketKey::Text("r".to_owned()), 3), (BucketKey::Text("x".to_owned()), 2)], 4));
+    }
+}
@@ -0,0 +1,47 @@
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct Token {
+    pub term: String,
+    pub position: usize,
+
+    // The byte range of the token in the original text. Used for highlighting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offsets: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Analyzer {
+    // Splits on whitespace, strips surrounding punctuation and lowercases each term
+    #[default]
+    Standard,
+
+    // Indexes the whole input as a single term
+    Keyword,
+}
+
+impl Analyzer {
+    pub fn analyze(&self, text: &str) -> Vec<Token> {
+        match self {
+            Analyzer::Standard => {
+                let mut current_position = 0;
+                text.split_whitespace().filter_map(|word| {
+                    let trimmed_word = word.trim_matches(|c: char| !c.is_alphanumeric());
+                    let term = trimmed_word.to_lowercase();
+
+                    if term.is_empty() || term.len() >= 100 {
+                        return None;
+                    }
+
+                    // The words are slices of the text, so their offsets can be found from their pointers
+                    let start = trimmed_word.as_ptr() as usize - text.as_ptr() as usize;
+
+                    current_position += 1;
+                    Some(Token { term, position: current_position, offsets: Some((start, start + trimmed_word.len())) })
+                }).collect()
+            }
+            Analyzer::Keyword => {
+                vec![Token { term: text.to_owned(), position: 1, offsets: Some((0, text.len())) }]
+            }
+        }
+    }
+}
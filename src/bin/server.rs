@@ -2,25 +2,39 @@
 
 #[macro_use] extern crate rocket;
 
-use std::collections::HashMap;
-use rocket::State;
+use std::borrow::Cow;
+use std::collections::{HashMap, BTreeMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use rocket::{Outcome, Request, State};
+use rocket::data::{self, Data, DataStream, FromDataSimple};
+use rocket::http::Status;
+use rocket::response::{self, status, Responder};
 use rocket_contrib::json::Json;
+use serde::de::DeserializeOwned;
 use fnv::FnvHashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex, RwLock, PoisonError};
 
-use sparrow::{Database, Document};
+use sparrow::{Database, DatabaseStats, Document, DocumentId};
+use sparrow::analysis::{Analyzer, Token};
 use sparrow::tsvector::{TSVector, TSVectorTerm};
 use sparrow::term_dictionary::{TermId, TermDictionary};
-use sparrow::data_dictionary::{FieldConfig, DataDictionary};
+use sparrow::data_dictionary::{FieldId, FieldType, FieldConfig, DataDictionary, MappingError};
+use sparrow::numeric_index::NumericValue;
 use sparrow::query::Query;
+use sparrow::sort::{self, Sort, SortKey, SortOrder, MissingOrder};
+use sparrow::aggregation::{self, Aggregation, AggregationResult, RangeBucket};
+use sparrow::query_string;
+use sparrow::highlight::{self, HighlightOptions};
+use sparrow::suggest;
+use sparrow::date::{self, DateUnit};
+use sparrow::persistence::{self, PersistenceError};
 
-#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
-pub struct Token {
-    pub term: String,
-    pub position: usize,
-}
-
-fn tokenvec_to_tsvector(tokenvec: &Vec<Token>, dict: &mut TermDictionary) -> TSVector {
+fn tokenvec_to_tsvector(tokenvec: &[Token], dict: &mut TermDictionary) -> TSVector {
     let mut terms: FnvHashMap<TermId, TSVectorTerm> = FnvHashMap::default();
 
     for token in tokenvec {
@@ -33,27 +47,235 @@ fn tokenvec_to_tsvector(tokenvec: &Vec<Token>, dict: &mut TermDictionary) -> TSV
     TSVector { terms, length: tokenvec.len() }
 }
 
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(untagged)]
+pub enum FieldValueSource {
+    Boolean(bool),
+    // JSON integers are read as i64 so that they don't lose precision. Other numbers are read as f64
+    Integer(i64),
+    Number(f64),
+    Text(String),
+    Texts(Vec<String>),
+    Completions(Vec<CompletionInputSource>),
+    Tokens(Vec<Token>),
+}
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct CompletionInputSource {
+    pub input: String,
+    #[serde(default = "default_completion_weight")]
+    pub weight: f32,
+}
+
+fn default_completion_weight() -> f32 {
+    1.0
+}
+
+impl FieldValueSource {
+    // Text is analysed on the server. Older clients send tokens that they have analysed themselves
+    fn as_tokens(&self, analyzer: Analyzer) -> Cow<'_, [Token]> {
+        match self {
+            FieldValueSource::Text(text) => Cow::Owned(analyzer.analyze(text)),
+            FieldValueSource::Tokens(tokens) => Cow::Borrowed(tokens),
+            _ => Cow::Owned(Vec::new()),
+        }
+    }
+
+    // The value that's kept for stored fields. Tokens are turned back into text
+    fn as_stored_value(&self) -> Option<serde_json::Value> {
+        match self {
+            FieldValueSource::Tokens(tokens) => Some(serde_json::Value::String(tokens_to_text(tokens))),
+            value => serde_json::to_value(value).ok(),
+        }
+    }
+}
+
+// Rebuilds the text of a field from its tokens
+// Tokens with offsets are put back where they were, so the offsets still line up for highlighting. The rest are
+// added after them in order of position
+fn tokens_to_text(tokens: &[Token]) -> String {
+    let mut text = String::new();
+
+    let mut tokens = tokens.iter().collect::<Vec<_>>();
+    tokens.sort_by_key(|token| (token.offsets.is_none(), token.offsets, token.position));
+
+    for token in tokens {
+        match token.offsets {
+            Some((start, _)) if start >= text.len() => {
+                let padding = start - text.len();
+                text.push_str(&" ".repeat(padding));
+            }
+            _ if !text.is_empty() => text.push(' '),
+            _ => {}
+        }
+
+        text.push_str(&token.term);
+    }
+
+    text
+}
+
 #[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct DocumentSource {
     pub pk: String,
-    pub fields: HashMap<String, Vec<Token>>,
+    pub fields: HashMap<String, FieldValueSource>,
+}
+
+// Problems with a document that stop it from being indexed
+#[derive(Debug, Clone, serde_derive::Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum DocumentError {
+    UnknownField {
+        field: String,
+    },
+    InvalidFieldValue {
+        field: String,
+        field_type: FieldType,
+    },
+}
+
+// Converts a whole number that was sent as a float, such as 1e3, into an integer
+fn as_integer(number: f64) -> Option<i64> {
+    if number.fract() == 0.0 && number >= i64::MIN as f64 && number < i64::MAX as f64 {
+        Some(number as i64)
+    } else {
+        None
+    }
+}
+
+// Checks that the value is something that as_document can index into a field of this type
+fn is_valid_field_value(field_type: FieldType, value: &FieldValueSource) -> bool {
+    match (field_type, value) {
+        (FieldType::Text, FieldValueSource::Text(_)) | (FieldType::Text, FieldValueSource::Tokens(_)) => true,
+        // An empty list of tokens is read as Texts, as it comes first in FieldValueSource
+        (FieldType::Text, FieldValueSource::Texts(texts)) => texts.is_empty(),
+        (FieldType::Integer, FieldValueSource::Integer(_)) | (FieldType::Float, FieldValueSource::Integer(_)) | (FieldType::Date, FieldValueSource::Integer(_)) => true,
+        (FieldType::Integer, FieldValueSource::Number(number)) => as_integer(*number).is_some(),
+        (FieldType::Float, FieldValueSource::Number(number)) => number.is_finite(),
+        (FieldType::Date, FieldValueSource::Text(text)) => date::parse_datetime(text).is_some(),
+        (FieldType::Date, FieldValueSource::Number(millis)) => as_integer(*millis).is_some(),
+        (FieldType::Keyword, FieldValueSource::Text(_)) | (FieldType::Keyword, FieldValueSource::Texts(_)) | (FieldType::Keyword, FieldValueSource::Tokens(_)) => true,
+        (FieldType::Boolean, FieldValueSource::Boolean(_)) => true,
+        (FieldType::Hierarchy, FieldValueSource::Text(_)) => true,
+        (FieldType::Hierarchy, FieldValueSource::Texts(texts)) => !texts.is_empty(),
+        (FieldType::Hierarchy, FieldValueSource::Tokens(tokens)) => !tokens.is_empty(),
+        (FieldType::Completion, FieldValueSource::Text(_)) | (FieldType::Completion, FieldValueSource::Texts(_)) | (FieldType::Completion, FieldValueSource::Completions(_)) => true,
+        _ => false,
+    }
 }
 
 impl DocumentSource {
+    // Finds the first field that doesn't exist or has a value that can't be indexed
+    // Note: as_document ignores these fields, so this is only needed if the document should be rejected instead
+    pub fn validate(&self, data_dict: &DataDictionary) -> Result<(), DocumentError> {
+        for (field, value) in &self.fields {
+            match data_dict.get_by_name(field) {
+                Some((_, field_config)) if !is_valid_field_value(field_config.field_type, value) => {
+                    return Err(DocumentError::InvalidFieldValue { field: field.clone(), field_type: field_config.field_type });
+                }
+                Some(_) => {}
+                None => return Err(DocumentError::UnknownField { field: field.clone() }),
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn as_document(&self, term_dict: &mut TermDictionary, data_dict: &DataDictionary) -> Document {
         let mut fields = FnvHashMap::default();
+        let mut numeric_fields = FnvHashMap::default();
+        let mut keyword_fields = FnvHashMap::default();
+        let mut hierarchy_fields = FnvHashMap::default();
+        let mut completion_fields = FnvHashMap::default();
+        let mut offsets = FnvHashMap::default();
+        let mut stored_fields = FnvHashMap::default();
         let mut copy_fields = FnvHashMap::default();
 
-        for (field, tokens) in &self.fields {
+        for (field, value) in &self.fields {
             if let Some((field_id, field_config)) = data_dict.get_by_name(field) {
-                let mut tsvector = tokenvec_to_tsvector(tokens, term_dict);
-                // Apply field boost and document length normalisation
-                // Note: we multiply the weight by the average field length at query time
-                tsvector.boost(field_config.boost / tsvector.length as f32);
-                fields.insert(field_id, tsvector);
+                if field_config.stored {
+                    if let Some(value) = value.as_stored_value() {
+                        stored_fields.insert(field_id, value);
+                    }
+                }
+
+                match (field_config.field_type, value) {
+                    (FieldType::Text, FieldValueSource::Text(_)) | (FieldType::Text, FieldValueSource::Tokens(_)) | (FieldType::Text, FieldValueSource::Texts(_)) => {
+                        let tokens = value.as_tokens(field_config.analyzer);
+                        let mut tsvector = tokenvec_to_tsvector(&tokens, term_dict);
+                        // Apply field boost and document length normalisation
+                        // Note: we multiply the weight by the average field length at query time
+                        tsvector.boost(field_config.boost / tsvector.length as f32);
+                        fields.insert(field_id, tsvector);
 
-                if !field_config.copy_to.is_empty() {
-                    copy_fields.insert(field_id, field_config.copy_to.clone());
+                        // Keep the offsets of each token for highlighting. Tokens sent by older clients don't have them
+                        let field_offsets = tokens.iter().filter_map(|token| token.offsets.map(|token_offsets| (token.position, token_offsets))).collect::<FnvHashMap<_, _>>();
+                        if !field_offsets.is_empty() {
+                            offsets.insert(field_id, field_offsets);
+                        }
+
+                        if !field_config.copy_to.is_empty() {
+                            copy_fields.insert(field_id, field_config.copy_to.clone());
+                        }
+                    }
+                    (FieldType::Integer, FieldValueSource::Integer(number)) | (FieldType::Date, FieldValueSource::Integer(number)) => {
+                        numeric_fields.insert(field_id, NumericValue::Integer(*number));
+                    }
+                    (FieldType::Integer, FieldValueSource::Number(number)) | (FieldType::Date, FieldValueSource::Number(number)) => {
+                        if let Some(number) = as_integer(*number) {
+                            numeric_fields.insert(field_id, NumericValue::Integer(number));
+                        }
+                    }
+                    (FieldType::Float, FieldValueSource::Integer(number)) => {
+                        numeric_fields.insert(field_id, NumericValue::Float(*number as f64));
+                    }
+                    (FieldType::Float, FieldValueSource::Number(number)) if number.is_finite() => {
+                        numeric_fields.insert(field_id, NumericValue::Float(*number));
+                    }
+                    (FieldType::Date, FieldValueSource::Text(text)) => {
+                        if let Some(millis) = date::parse_datetime(text) {
+                            numeric_fields.insert(field_id, NumericValue::Integer(millis));
+                        }
+                    }
+                    (FieldType::Keyword, FieldValueSource::Text(text)) => {
+                        keyword_fields.insert(field_id, vec![term_dict.get_or_insert(text)]);
+                    }
+                    (FieldType::Keyword, FieldValueSource::Texts(texts)) => {
+                        keyword_fields.insert(field_id, texts.iter().map(|text| term_dict.get_or_insert(text)).collect());
+                    }
+                    (FieldType::Boolean, FieldValueSource::Boolean(value)) => {
+                        keyword_fields.insert(field_id, vec![term_dict.get_or_insert(if *value { "true" } else { "false" })]);
+                    }
+                    // Hierarchies are given as their ancestor chain, starting with the root. For example: ["wagtailcore_page", "blog_blogpage"]
+                    (FieldType::Hierarchy, FieldValueSource::Text(text)) => {
+                        let term = term_dict.get_or_insert(text);
+                        keyword_fields.insert(field_id, vec![term]);
+                        hierarchy_fields.insert(field_id, vec![term]);
+                    }
+                    (FieldType::Hierarchy, FieldValueSource::Texts(texts)) if !texts.is_empty() => {
+                        let chain = texts.iter().map(|text| term_dict.get_or_insert(text)).collect::<Vec<_>>();
+                        keyword_fields.insert(field_id, vec![chain[chain.len() - 1]]);
+                        hierarchy_fields.insert(field_id, chain);
+                    }
+                    (FieldType::Completion, FieldValueSource::Text(text)) => {
+                        completion_fields.insert(field_id, vec![(text.clone(), default_completion_weight())]);
+                    }
+                    (FieldType::Completion, FieldValueSource::Texts(texts)) => {
+                        completion_fields.insert(field_id, texts.iter().map(|text| (text.clone(), default_completion_weight())).collect());
+                    }
+                    (FieldType::Completion, FieldValueSource::Completions(inputs)) => {
+                        completion_fields.insert(field_id, inputs.iter().map(|input| (input.input.clone(), input.weight)).collect());
+                    }
+                    // Older clients send keywords as tokens
+                    (FieldType::Keyword, FieldValueSource::Tokens(tokens)) => {
+                        keyword_fields.insert(field_id, tokens.iter().map(|token| term_dict.get_or_insert(&token.term)).collect());
+                    }
+                    (FieldType::Hierarchy, FieldValueSource::Tokens(tokens)) if !tokens.is_empty() => {
+                        let chain = tokens.iter().map(|token| term_dict.get_or_insert(&token.term)).collect::<Vec<_>>();
+                        keyword_fields.insert(field_id, vec![chain[chain.len() - 1]]);
+                        hierarchy_fields.insert(field_id, chain);
+                    }
+                    _ => {}
                 }
             }
         }
@@ -71,7 +293,7 @@ impl DocumentSource {
             }
         }
 
-        Document { fields }
+        Document { fields, numeric_fields, keyword_fields, hierarchy_fields, completion_fields, offsets, stored_fields, source: None }
     }
 }
 
@@ -83,10 +305,27 @@ pub enum QuerySource {
         field: String,
         term: String,
     },
+    Terms {
+        field: String,
+        terms: Vec<String>,
+    },
     Phrase {
         field: String,
         terms: Vec<String>,
     },
+    Exists {
+        field: String,
+    },
+    Missing {
+        field: String,
+    },
+    Range {
+        field: String,
+        gte: Option<RangeBoundSource>,
+        lte: Option<RangeBoundSource>,
+        gt: Option<RangeBoundSource>,
+        lt: Option<RangeBoundSource>,
+    },
     Or(Vec<QuerySource>),
     And(Vec<QuerySource>),
     Filter {
@@ -101,13 +340,144 @@ pub enum QuerySource {
         query: Box<QuerySource>,
         boost: f32
     },
+    ConstantScore {
+        query: Box<QuerySource>,
+        score: f32,
+    },
+    MultiMatch {
+        query: String,
+        fields: Vec<String>,
+        #[serde(rename = "type", default)]
+        match_type: MultiMatchType,
+    },
+    SimpleQueryString {
+        query: String,
+        fields: Vec<String>,
+    },
+    // Filters by the "content_type" field. The model is given as "app_model", for example: "blog_blogpage"
+    ContentType {
+        model: String,
+        #[serde(default = "default_include_subclasses")]
+        include_subclasses: bool,
+    },
+}
+
+fn default_include_subclasses() -> bool {
+    true
+}
+
+// Range bounds can be given as numbers, or as strings which is useful for dates. For example: "now-7d/d"
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(untagged)]
+pub enum RangeBoundSource {
+    Integer(i64),
+    Number(f64),
+    Text(String),
+}
+
+impl RangeBoundSource {
+    // Converts the bound into the value that's stored in the NumericIndex
+    // Date math is rounded up for "lte" and "gt" bounds so that they include the whole of the rounded unit
+    fn as_value(&self, field_type: FieldType, round_up: bool) -> Option<NumericValue> {
+        match (field_type, self) {
+            (_, RangeBoundSource::Integer(number)) => Some(NumericValue::Integer(*number)),
+            (_, RangeBoundSource::Number(number)) => Some(NumericValue::Float(*number)),
+            (FieldType::Date, RangeBoundSource::Text(text)) => date::evaluate_date_math(text, now_millis(), round_up).map(NumericValue::Integer),
+            (_, RangeBoundSource::Text(text)) => text.parse().map(NumericValue::Integer).or_else(|_| text.parse().map(NumericValue::Float)).ok(),
+        }
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_millis() as i64).unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiMatchType {
+    // Scores each document by its best matching field
+    #[default]
+    BestFields,
+
+    // Adds together the scores of all matching fields
+    MostFields,
+
+    // Treats the fields as one big field, so each term is scored with the same IDF in all of them
+    CrossFields,
+
+    // Like BestFields, but each field must contain the whole text as a phrase
+    Phrase,
+}
+
+// Parses a field name with an optional boost suffix. For example: "title^2"
+fn parse_field_boost(field: &str) -> (&str, f32) {
+    if let Some((name, boost)) = field.rsplit_once('^') {
+        if let Ok(boost) = boost.parse() {
+            return (name, boost);
+        }
+    }
+
+    (field, 1.0)
+}
+
+// Runs the text through the field's analyzer and looks up each term
+// Terms that aren't in the dictionary are returned as None
+fn analyze_query_text(text: &str, analyzer: Analyzer, term_dict: &TermDictionary) -> Vec<Option<TermId>> {
+    analyzer.analyze(text).into_iter().map(|token| term_dict.terms.get(&token.term).cloned()).collect()
+}
+
+fn multi_match_query(text: &str, fields: &[String], match_type: MultiMatchType, term_dict: &TermDictionary, data_dict: &DataDictionary) -> Query {
+    let fields = fields.iter().map(|field| parse_field_boost(field)).filter_map(|(name, boost)| {
+        data_dict.get_by_name(name).map(|(field_id, field_config)| (field_id, field_config.analyzer, boost))
+    }).collect::<Vec<(FieldId, Analyzer, f32)>>();
+
+    match match_type {
+        MultiMatchType::BestFields | MultiMatchType::MostFields => {
+            let field_queries = fields.iter().map(|(field_id, analyzer, boost)| {
+                let term_ids = analyze_query_text(text, *analyzer, term_dict);
+                Query::boost(Query::or(term_ids.into_iter().flatten().map(|term_id| Query::term(*field_id, term_id)).collect()), *boost)
+            }).collect();
+
+            if match_type == MultiMatchType::BestFields {
+                Query::dis_max(field_queries)
+            } else {
+                Query::or(field_queries)
+            }
+        }
+        MultiMatchType::Phrase => {
+            Query::dis_max(fields.iter().map(|(field_id, analyzer, boost)| {
+                match analyze_query_text(text, *analyzer, term_dict).into_iter().collect::<Option<Vec<_>>>() {
+                    Some(term_ids) if !term_ids.is_empty() => Query::boost(Query::phrase(*field_id, term_ids), *boost),
+                    _ => Query::match_none(),
+                }
+            }).collect())
+        }
+        MultiMatchType::CrossFields => {
+            // Fields that use different analyzers might produce different terms, so each analyzer gets its own group
+            let mut groups: Vec<(Analyzer, Vec<(FieldId, f32)>)> = Vec::new();
+            for (field_id, analyzer, boost) in &fields {
+                match groups.iter_mut().find(|(group_analyzer, _)| group_analyzer == analyzer) {
+                    Some((_, group_fields)) => group_fields.push((*field_id, *boost)),
+                    None => groups.push((*analyzer, vec![(*field_id, *boost)])),
+                }
+            }
+
+            Query::dis_max(groups.into_iter().map(|(analyzer, group_fields)| {
+                let term_ids = analyze_query_text(text, analyzer, term_dict);
+                Query::or(term_ids.into_iter().flatten().map(|term_id| Query::blended_term(group_fields.clone(), term_id)).collect())
+            }).collect())
+        }
+    }
 }
 
 impl QuerySource {
-    pub fn as_query(&self, term_dict: &TermDictionary, data_dict: &DataDictionary) -> Query {
+    pub fn as_query(&self, db: &Database) -> Query {
+        let term_dict = &db.term_dictionary;
+        let data_dict = &db.data_dictionary;
+
         match self {
             QuerySource::MatchAll => Query::match_all(),
-            QuerySource::MatchNone => Query::match_all(),
+            QuerySource::MatchNone => Query::match_none(),
             QuerySource::Term { field, term } => {
                 if let Some(field_id) = data_dict.field_names.get(field) {
                     if let Some(term_id) = term_dict.terms.get(term) {
@@ -117,6 +487,13 @@ impl QuerySource {
 
                 Query::match_none()
             }
+            QuerySource::Terms { field, terms } => {
+                if let Some(field_id) = data_dict.field_names.get(field) {
+                    return Query::term_set(*field_id, terms.iter().filter_map(|term| term_dict.terms.get(term).cloned()).collect());
+                }
+
+                Query::match_none()
+            }
             QuerySource::Phrase { field, terms } => {
                 if let Some(field_id) = data_dict.field_names.get(field) {
                     if let Some(term_ids) = terms.into_iter().map(|term| term_dict.terms.get(term).cloned()).collect::<Option<Vec<_>>>() {
@@ -126,78 +503,1920 @@ impl QuerySource {
 
                 Query::match_none()
             }
+            QuerySource::Exists { field } => {
+                match data_dict.get_by_name(field) {
+                    Some((field_id, _)) => Query::exists(field_id),
+                    None => Query::match_none(),
+                }
+            }
+            QuerySource::Missing { field } => {
+                match data_dict.get_by_name(field) {
+                    Some((field_id, _)) => Query::missing(field_id),
+                    None => Query::match_all(),
+                }
+            }
+            QuerySource::Range { field, gte, lte, gt, lt } => {
+                if let Some((field_id, field_config)) = data_dict.get_by_name(field) {
+                    let field_type = field_config.field_type;
+
+                    if field_type.is_numeric() || field_type == FieldType::Date {
+                        // If any of the bounds are invalid, the range matches nothing
+                        let bound = |bound: &Option<RangeBoundSource>, round_up| match bound {
+                            Some(bound) => bound.as_value(field_type, round_up).map(Some),
+                            None => Some(None),
+                        };
+
+                        if let (Some(gte), Some(lte), Some(gt), Some(lt)) = (bound(gte, false), bound(lte, true), bound(gt, true), bound(lt, false)) {
+                            return Query::range(field_id, gte, lte, gt, lt);
+                        }
+                    }
+                }
+
+                Query::match_none()
+            }
             QuerySource::Or(queries) => {
-                Query::or(queries.iter().map(|query| query.as_query(&term_dict, &data_dict)).collect())
+                Query::or(queries.iter().map(|query| query.as_query(db)).collect())
             }
             QuerySource::And(queries) => {
-                Query::and(queries.iter().map(|query| query.as_query(&term_dict, &data_dict)).collect())
+                Query::and(queries.iter().map(|query| query.as_query(db)).collect())
             }
             QuerySource::Filter { query, filter } => {
-                Query::filter(query.as_query(&term_dict, &data_dict), filter.as_query(&term_dict, &data_dict))
+                Query::filter(query.as_query(db), filter.as_query(db))
             }
             QuerySource::Exclude { query, filter } => {
-                Query::exclude(query.as_query(&term_dict, &data_dict), filter.as_query(&term_dict, &data_dict))
+                Query::exclude(query.as_query(db), filter.as_query(db))
             }
             QuerySource::Boost { query, boost } => {
-                Query::boost(query.as_query(&term_dict, &data_dict), *boost)
+                Query::boost(query.as_query(db), *boost)
+            }
+            QuerySource::ConstantScore { query, score } => {
+                Query::constant_score(query.as_query(db), *score)
+            }
+            QuerySource::MultiMatch { query, fields, match_type } => {
+                multi_match_query(query, fields, *match_type, term_dict, data_dict)
+            }
+            QuerySource::SimpleQueryString { query, fields } => {
+                let fields = fields.iter().map(|field| parse_field_boost(field)).filter_map(|(name, boost)| {
+                    data_dict.get_by_name(name).map(|(field_id, _)| (field_id, boost))
+                }).collect::<Vec<_>>();
+
+                query_string::parse_simple(query, &fields, term_dict, data_dict)
+            }
+            QuerySource::ContentType { model, include_subclasses } => {
+                if let Some(field_id) = data_dict.field_names.get("content_type") {
+                    if let Some(term_id) = term_dict.terms.get(model) {
+                        if *include_subclasses {
+                            return Query::term_set(*field_id, db.descendants(*field_id, *term_id));
+                        } else {
+                            return Query::term(*field_id, *term_id);
+                        }
+                    }
+                }
+
+                Query::match_none()
             }
         }
     }
 }
 
-#[get("/")]
-fn index() -> &'static str {
-    "Hello, world!"
+#[derive(Debug, Clone, Copy, PartialEq, serde_derive::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Severity {
+    // The query can't be run as it was intended, for example it refers to a field that doesn't exist
+    Error,
+
+    // The query can be run, but part of it can never match. For example, a term that isn't in any document
+    Warning,
 }
 
-#[post("/insert", format = "application/json", data = "<doc>")]
-fn insert(doc: Json<DocumentSource>) -> &'static str {
-    "Hello, world!"
+#[derive(Debug, Clone, serde_derive::Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+enum QueryProblemKind {
+    UnknownField {
+        field: String,
+    },
+    UnresolvedTerm {
+        field: String,
+        term: String,
+    },
+    InvalidBoost {
+        boost: f32,
+    },
+    InvalidScore {
+        score: f32,
+    },
+    InvalidFieldType {
+        field: String,
+        field_type: FieldType,
+    },
+    InvalidRangeBound {
+        field: String,
+        bound: String,
+    },
+    InvalidQueryString {
+        message: String,
+    },
 }
 
-#[post("/bulk", format = "application/json", data = "<docs>")]
-fn bulk(db: State<RwLock<Database>>, docs: Json<Vec<DocumentSource>>) -> &'static str {
-    let mut db = db.write().unwrap();
-    let data_dictionary = db.data_dictionary.clone();
-    for source in docs.iter() {
-        let doc = source.as_document(&mut db.term_dictionary, &data_dictionary);
-        db.insert_document(source.pk.to_owned(), doc);
+impl QueryProblemKind {
+    fn severity(&self) -> Severity {
+        match self {
+            QueryProblemKind::UnresolvedTerm { .. } | QueryProblemKind::InvalidQueryString { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
     }
+}
 
-    "Hello, world!"
+// Something in a query that as_query would silently turn into a query that matches nothing
+// The path is where in the query the problem is. For example: "And.1.Filter.filter.Term"
+#[derive(Debug, Clone, serde_derive::Serialize)]
+struct QueryProblem {
+    severity: Severity,
+    // Only included when the query is validated against an alias that points at more than one index
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<String>,
+    path: String,
+    #[serde(flatten)]
+    kind: QueryProblemKind,
+}
+
+impl QuerySource {
+    // The name of the query in JSON
+    fn name(&self) -> &'static str {
+        match self {
+            QuerySource::MatchAll => "MatchAll",
+            QuerySource::MatchNone => "MatchNone",
+            QuerySource::Term { .. } => "Term",
+            QuerySource::Terms { .. } => "Terms",
+            QuerySource::Phrase { .. } => "Phrase",
+            QuerySource::Exists { .. } => "Exists",
+            QuerySource::Missing { .. } => "Missing",
+            QuerySource::Range { .. } => "Range",
+            QuerySource::Or(_) => "Or",
+            QuerySource::And(_) => "And",
+            QuerySource::Filter { .. } => "Filter",
+            QuerySource::Exclude { .. } => "Exclude",
+            QuerySource::Boost { .. } => "Boost",
+            QuerySource::ConstantScore { .. } => "ConstantScore",
+            QuerySource::MultiMatch { .. } => "MultiMatch",
+            QuerySource::SimpleQueryString { .. } => "SimpleQueryString",
+            QuerySource::ContentType { .. } => "ContentType",
+        }
+    }
+
+    // Finds the fields, terms and boosts in the query that as_query can't use
+    fn validate(&self, db: &Database) -> Vec<QueryProblem> {
+        let mut problems = Vec::new();
+        self.collect_problems(db, "", &mut problems);
+        problems
+    }
+
+    // Rejects the query if it has any errors. Warnings are allowed as they can't make the query match more than intended
+    fn check(&self, db: &Database) -> Result<(), ApiError> {
+        let errors = self.validate(db).into_iter().filter(|problem| problem.severity == Severity::Error).collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return Err(ServerError::InvalidQuery { problems: errors }.into());
+        }
+
+        Ok(())
+    }
+
+    fn collect_problems(&self, db: &Database, parent_path: &str, problems: &mut Vec<QueryProblem>) {
+        let term_dict = &db.term_dictionary;
+        let data_dict = &db.data_dictionary;
+        let path = if parent_path.is_empty() { self.name().to_owned() } else { format!("{}.{}", parent_path, self.name()) };
+        let mut kinds = Vec::new();
+        let mut children = Vec::new();
+
+        match self {
+            QuerySource::MatchAll | QuerySource::MatchNone => {}
+            QuerySource::Term { field, term } => check_field_terms(field, std::slice::from_ref(term), db, &mut kinds),
+            QuerySource::Terms { field, terms } | QuerySource::Phrase { field, terms } => check_field_terms(field, terms, db, &mut kinds),
+            QuerySource::Exists { field } | QuerySource::Missing { field } => check_field_terms(field, &[], db, &mut kinds),
+            QuerySource::Range { field, gte, lte, gt, lt } => {
+                match data_dict.get_by_name(field) {
+                    Some((_, field_config)) if field_config.field_type.is_numeric() || field_config.field_type == FieldType::Date => {
+                        for (name, bound, round_up) in &[("gte", gte, false), ("lte", lte, true), ("gt", gt, true), ("lt", lt, false)] {
+                            if let Some(bound) = bound {
+                                if bound.as_value(field_config.field_type, *round_up).is_none() {
+                                    kinds.push(QueryProblemKind::InvalidRangeBound { field: field.clone(), bound: (*name).to_owned() });
+                                }
+                            }
+                        }
+                    }
+                    Some((_, field_config)) => kinds.push(QueryProblemKind::InvalidFieldType { field: field.clone(), field_type: field_config.field_type }),
+                    None => kinds.push(QueryProblemKind::UnknownField { field: field.clone() }),
+                }
+            }
+            QuerySource::Or(queries) | QuerySource::And(queries) => {
+                children.extend(queries.iter().enumerate().map(|(i, query)| (query, i.to_string())));
+            }
+            QuerySource::Filter { query, filter } | QuerySource::Exclude { query, filter } => {
+                children.push((query, "query".to_owned()));
+                children.push((filter, "filter".to_owned()));
+            }
+            QuerySource::Boost { query, boost } => {
+                if !boost.is_finite() || *boost < 0.0 {
+                    kinds.push(QueryProblemKind::InvalidBoost { boost: *boost });
+                }
+
+                children.push((query, "query".to_owned()));
+            }
+            QuerySource::ConstantScore { query, score } => {
+                if !score.is_finite() {
+                    kinds.push(QueryProblemKind::InvalidScore { score: *score });
+                }
+
+                children.push((query, "query".to_owned()));
+            }
+            QuerySource::MultiMatch { query, fields, .. } => {
+                for (field, field_id, field_config) in check_fields(fields, db, &mut kinds) {
+                    for token in field_config.analyzer.analyze(query) {
+                        if term_dict.get_in_field(field_id, &token.term).is_none() {
+                            kinds.push(QueryProblemKind::UnresolvedTerm { field: field.to_owned(), term: token.term });
+                        }
+                    }
+                }
+            }
+            QuerySource::SimpleQueryString { query, fields } => {
+                let fields = check_fields(fields, db, &mut kinds).into_iter().map(|(_, field_id, _)| (field_id, 1.0)).collect::<Vec<_>>();
+
+                // Invalid syntax isn't an error as the words in the query are searched for instead
+                if let Err(error) = query_string::parse(query, &fields, term_dict, data_dict) {
+                    kinds.push(QueryProblemKind::InvalidQueryString { message: error.to_string() });
+                }
+            }
+            QuerySource::ContentType { model, .. } => check_field_terms("content_type", std::slice::from_ref(model), db, &mut kinds),
+        }
+
+        problems.extend(kinds.into_iter().map(|kind| QueryProblem { severity: kind.severity(), index: None, path: path.clone(), kind }));
+
+        for (query, key) in children {
+            query.collect_problems(db, &format!("{}.{}", path, key), problems);
+        }
+    }
+}
+
+// Checks that the field exists and reports any of the terms that aren't in it
+fn check_field_terms(field: &str, terms: &[String], db: &Database, problems: &mut Vec<QueryProblemKind>) {
+    let field_id = match db.data_dictionary.get_by_name(field) {
+        Some((field_id, _)) => field_id,
+        None => {
+            problems.push(QueryProblemKind::UnknownField { field: field.to_owned() });
+            return;
+        }
+    };
+
+    for term in terms {
+        if db.term_dictionary.get_in_field(field_id, term).is_none() {
+            problems.push(QueryProblemKind::UnresolvedTerm { field: field.to_owned(), term: term.clone() });
+        }
+    }
+}
+
+// Checks a list of fields with optional boosts, such as "title^2", and returns the ones that exist
+fn check_fields<'a>(fields: &'a [String], db: &'a Database, problems: &mut Vec<QueryProblemKind>) -> Vec<(&'a str, FieldId, &'a FieldConfig)> {
+    fields.iter().map(|field| parse_field_boost(field)).filter_map(|(name, boost)| {
+        if !boost.is_finite() || boost < 0.0 {
+            problems.push(QueryProblemKind::InvalidBoost { boost });
+        }
+
+        match db.data_dictionary.get_by_name(name) {
+            Some((field_id, field_config)) => Some((name, field_id, field_config)),
+            None => {
+                problems.push(QueryProblemKind::UnknownField { field: name.to_owned() });
+                None
+            }
+        }
+    }).collect()
+}
+
+// Errors from the server itself, rather than from the database or from the documents and mappings that were sent to it
+#[derive(Debug, Clone, serde_derive::Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+enum ServerError {
+    BadRequest,
+    NotFound,
+    UnprocessableEntity,
+    IndexNotFound {
+        index: String,
+    },
+    IndexAlreadyExists {
+        index: String,
+    },
+    InvalidIndexName {
+        index: String,
+    },
+    InvalidAliasName {
+        alias: String,
+    },
+    AliasNotFound {
+        alias: String,
+        index: String,
+    },
+    // Requests that only work on one index can't be sent to an alias that points at several
+    AliasHasMultipleIndexes {
+        alias: String,
+    },
+    DocumentNotFound {
+        pk: String,
+    },
+    // An aggregation has a setting that can't be used, such as a range bound that isn't a valid number or date
+    InvalidAggregation {
+        aggregation: String,
+        message: String,
+    },
+    // Only fields with doc values can be sorted on. For example, text fields can't be
+    FieldNotSortable {
+        field: String,
+        field_type: FieldType,
+    },
+    // Partial updates are merged into the source of the document, so they only work on indexes that store it
+    SourceNotStored {
+        pk: String,
+    },
+    // A strict search or a delete by query was given a query with errors in it
+    InvalidQuery {
+        problems: Vec<QueryProblem>,
+    },
+    InvalidJson {
+        message: String,
+    },
+    PayloadTooLarge {
+        limit: u64,
+    },
+    LockPoisoned,
+    PersistenceFailed {
+        message: String,
+    },
+    Internal,
 }
 
+// All of the errors that a request can fail with. Each kind of error is returned as JSON with its name in the "error" field
 #[derive(Debug, serde_derive::Serialize)]
-struct SearchResult {
-    pk: String,
-    score: f32,
+#[serde(untagged)]
+enum ApiError {
+    Server(ServerError),
+    Database(sparrow::Error),
+    Mapping(MappingError),
+    Document(DocumentError),
+}
+
+impl ApiError {
+    fn status(&self) -> Status {
+        match self {
+            ApiError::Server(error) => match error {
+                ServerError::BadRequest | ServerError::InvalidIndexName { .. } | ServerError::InvalidAliasName { .. } | ServerError::AliasHasMultipleIndexes { .. } | ServerError::InvalidQuery { .. } | ServerError::InvalidJson { .. } | ServerError::FieldNotSortable { .. } | ServerError::InvalidAggregation { .. } => Status::BadRequest,
+                ServerError::NotFound | ServerError::IndexNotFound { .. } | ServerError::AliasNotFound { .. } | ServerError::DocumentNotFound { .. } => Status::NotFound,
+                ServerError::IndexAlreadyExists { .. } => Status::Conflict,
+                ServerError::PayloadTooLarge { .. } => Status::PayloadTooLarge,
+                ServerError::UnprocessableEntity | ServerError::SourceNotStored { .. } => Status::UnprocessableEntity,
+                ServerError::LockPoisoned | ServerError::PersistenceFailed { .. } | ServerError::Internal => Status::InternalServerError,
+            },
+            ApiError::Database(error) => match error {
+                sparrow::Error::UnknownField { .. } | sparrow::Error::InvalidQuery { .. } => Status::BadRequest,
+                sparrow::Error::DocumentNotFound { .. } => Status::NotFound,
+            },
+            ApiError::Mapping(MappingError::IncompatibleChange { .. }) => Status::Conflict,
+            ApiError::Mapping(_) | ApiError::Document(_) => Status::UnprocessableEntity,
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        status::Custom(self.status(), Json(self)).respond_to(request)
+    }
 }
 
-#[post("/search", format = "application/json", data = "<query>")]
-fn search(db: State<RwLock<Database>>, query: Json<QuerySource>) -> Json<Vec<SearchResult>> {
-    let db = db.read().unwrap();
+impl From<ServerError> for ApiError {
+    fn from(error: ServerError) -> ApiError {
+        ApiError::Server(error)
+    }
+}
 
-    let mut documents = db.query(&query.as_query(&db.term_dictionary, &db.data_dictionary));
-    documents.sort_by(|a,b| a.1.partial_cmp(&b.1).unwrap().reverse());
+impl From<sparrow::Error> for ApiError {
+    fn from(error: sparrow::Error) -> ApiError {
+        ApiError::Database(error)
+    }
+}
 
-    Json(documents.into_iter().map(|(document_id, score)| SearchResult { pk: db.id_to_pk.get(&document_id).expect("Document does not have PK").to_owned(), score }).collect::<Vec<SearchResult>>())
+impl From<MappingError> for ApiError {
+    fn from(error: MappingError) -> ApiError {
+        ApiError::Mapping(error)
+    }
 }
 
-#[post("/reset")]
-fn reset() -> &'static str {
-    "Hello, world!"
+impl From<DocumentError> for ApiError {
+    fn from(error: DocumentError) -> ApiError {
+        ApiError::Document(error)
+    }
 }
 
-fn main() {
-    let mut db = Database::default();
+impl From<PersistenceError> for ApiError {
+    fn from(error: PersistenceError) -> ApiError {
+        ApiError::Server(ServerError::PersistenceFailed { message: error.to_string() })
+    }
+}
 
-    let all_text_field = db.data_dictionary.insert("all_text".to_owned(), FieldConfig::default());
-    db.data_dictionary.insert("pk".to_owned(), FieldConfig::default());
-    db.data_dictionary.insert("content_type".to_owned(), FieldConfig::default());
-    db.data_dictionary.insert("_partials".to_owned(), FieldConfig::default());
-    db.data_dictionary.insert("name".to_owned(), FieldConfig::default().boost(2.0).copy_to(all_text_field));
-    db.data_dictionary.insert("title".to_owned(), FieldConfig::default().boost(2.0).copy_to(all_text_field));
-    db.data_dictionary.insert("summary".to_owned(), FieldConfig::default().copy_to(all_text_field));
+impl From<io::Error> for ApiError {
+    fn from(error: io::Error) -> ApiError {
+        ApiError::Server(ServerError::PersistenceFailed { message: error.to_string() })
+    }
+}
 
-    rocket::ignite().manage(RwLock::new(db)).mount("/", routes![index, insert, bulk, search, reset]).launch();
+// A thread panicked while holding the lock, so the data behind it may be half updated
+impl<T> From<PoisonError<T>> for ApiError {
+    fn from(_: PoisonError<T>) -> ApiError {
+        ApiError::Server(ServerError::LockPoisoned)
+    }
+}
+
+// The size limit of request bodies if the "json" limit isn't set in the Rocket config
+const DEFAULT_JSON_LIMIT: u64 = 1024 * 1024;
+
+// A JSON request body. Unlike rocket_contrib's Json, bodies that are too large or can't be parsed are reported with an ApiError
+#[derive(Debug)]
+struct JsonBody<T>(T);
+
+// Like JsonBody, but the body can be left out. An empty body is read as None
+#[derive(Debug)]
+struct OptionalJsonBody<T>(Option<T>);
+
+// Reads and parses the body, unless it is empty
+fn read_json_body<T: DeserializeOwned>(request: &Request, data: Data) -> Result<Option<T>, ApiError> {
+    let limit = request.limits().get("json").unwrap_or(DEFAULT_JSON_LIMIT);
+
+    // Read one byte past the limit to find out if the body is too large
+    let mut body = String::new();
+    match data.open().take(limit + 1).read_to_string(&mut body) {
+        Ok(_) if body.len() as u64 > limit => Err(ServerError::PayloadTooLarge { limit }.into()),
+        Ok(_) if body.trim().is_empty() => Ok(None),
+        Ok(_) => serde_json::from_str(&body).map(Some).map_err(|error| ServerError::InvalidJson { message: error.to_string() }.into()),
+        Err(error) => Err(ServerError::InvalidJson { message: error.to_string() }.into()),
+    }
+}
+
+impl<T: DeserializeOwned> FromDataSimple for JsonBody<T> {
+    type Error = ApiError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<JsonBody<T>, ApiError> {
+        let result = read_json_body(request, data).and_then(|body| {
+            body.map(JsonBody).ok_or_else(|| ServerError::InvalidJson { message: "the request body is empty".to_owned() }.into())
+        });
+
+        match result {
+            Ok(body) => Outcome::Success(body),
+            Err(error) => Outcome::Failure((error.status(), error)),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> FromDataSimple for OptionalJsonBody<T> {
+    type Error = ApiError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<OptionalJsonBody<T>, ApiError> {
+        match read_json_body(request, data) {
+            Ok(body) => Outcome::Success(OptionalJsonBody(body)),
+            Err(error) => Outcome::Failure((error.status(), error)),
+        }
+    }
+}
+
+// A newline delimited JSON request body, which is read one line at a time instead of all at once
+// Each line is limited to the "json" limit, but there is no limit on the size of the whole body
+struct NdjsonBody {
+    reader: BufReader<DataStream>,
+    line_limit: u64,
+}
+
+enum NdjsonLine {
+    Line(Vec<u8>),
+    TooLarge,
+    End,
+}
+
+impl FromDataSimple for NdjsonBody {
+    type Error = ApiError;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<NdjsonBody, ApiError> {
+        let line_limit = request.limits().get("json").unwrap_or(DEFAULT_JSON_LIMIT);
+
+        Outcome::Success(NdjsonBody { reader: BufReader::new(data.open()), line_limit })
+    }
+}
+
+impl NdjsonBody {
+    fn read_line(&mut self) -> io::Result<NdjsonLine> {
+        // Read one byte past the limit to find out if the line is too large
+        let mut line = Vec::new();
+        if (&mut self.reader).take(self.line_limit + 1).read_until(b'\n', &mut line)? == 0 {
+            return Ok(NdjsonLine::End);
+        }
+
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else if line.len() as u64 > self.line_limit {
+            // Skip the rest of the line so that the lines after it can still be read
+            loop {
+                let buffer = self.reader.fill_buf()?;
+                match buffer.iter().position(|byte| *byte == b'\n') {
+                    Some(position) => {
+                        self.reader.consume(position + 1);
+                        break;
+                    }
+                    None if buffer.is_empty() => break,
+                    None => {
+                        let length = buffer.len();
+                        self.reader.consume(length);
+                    }
+                }
+            }
+
+            return Ok(NdjsonLine::TooLarge);
+        }
+
+        Ok(NdjsonLine::Line(line))
+    }
+}
+
+// The indexes that the server is hosting, by name
+// Each index has its own lock so that a long running request on one index doesn't block the others
+#[derive(Debug)]
+struct IndexRegistry {
+    // Each index is saved into a subdirectory of this, named after the index
+    data_dir: PathBuf,
+
+    // Held while an index is being saved or its directory removed
+    // This stops saves from finishing out of order and stops a dropped index from being written back
+    // Note: this must be taken before any of the other locks
+    saves: Mutex<()>,
+
+    // Note: when both locks are needed, the aliases lock must be taken first
+    aliases: RwLock<HashMap<String, Vec<String>>>,
+    indexes: RwLock<HashMap<String, Arc<RwLock<Database>>>>,
+}
+
+const ALIASES_FILENAME: &str = "aliases.json";
+
+// An index along with its name
+type NamedIndex = (String, Arc<RwLock<Database>>);
+
+// Problems with the data directory that stop the server from starting
+// Anything that can't be loaded would be overwritten later on, so the server doesn't start without it
+#[derive(Debug)]
+enum OpenError {
+    ReadDirectory(io::Error),
+    LoadIndex {
+        index: String,
+        error: PersistenceError,
+    },
+    LoadAliases(io::Error),
+    InvalidAliases(serde_json::Error),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpenError::ReadDirectory(error) => write!(f, "Failed to read the data directory: {}", error),
+            OpenError::LoadIndex { index, error } => write!(f, "Failed to load index '{}': {}", index, error),
+            OpenError::LoadAliases(error) => write!(f, "Failed to read {}: {}", ALIASES_FILENAME, error),
+            OpenError::InvalidAliases(error) => write!(f, "Failed to parse {}: {}", ALIASES_FILENAME, error),
+        }
+    }
+}
+
+fn load_aliases(data_dir: &Path) -> Result<HashMap<String, Vec<String>>, OpenError> {
+    match fs::read_to_string(data_dir.join(ALIASES_FILENAME)) {
+        Ok(aliases) => serde_json::from_str(&aliases).map_err(OpenError::InvalidAliases),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(error) => Err(OpenError::LoadAliases(error)),
+    }
+}
+
+// Writes the aliases to a temporary file first so a crash can't leave a half written file behind
+fn save_aliases(data_dir: &Path, aliases: &HashMap<String, Vec<String>>) -> std::io::Result<()> {
+    fs::create_dir_all(data_dir)?;
+    let temporary_path = data_dir.join(format!("{}.tmp", ALIASES_FILENAME));
+    fs::write(&temporary_path, serde_json::to_string(aliases)?)?;
+    fs::rename(temporary_path, data_dir.join(ALIASES_FILENAME))
+}
+
+impl IndexRegistry {
+    // Loads all of the indexes that have been saved into the data directory
+    // A data directory that doesn't exist yet is created when the first index is saved
+    fn open(data_dir: PathBuf) -> Result<IndexRegistry, OpenError> {
+        let mut indexes = HashMap::new();
+
+        let entries = match fs::read_dir(&data_dir) {
+            Ok(entries) => entries.collect::<Result<Vec<_>, _>>().map_err(OpenError::ReadDirectory)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(OpenError::ReadDirectory(error)),
+        };
+
+        for entry in entries {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            if !is_valid_index_name(&name) || !persistence::exists(&entry.path()) {
+                continue;
+            }
+
+            let db = persistence::load(&entry.path()).map_err(|error| OpenError::LoadIndex { index: name.clone(), error })?;
+            indexes.insert(name, Arc::new(RwLock::new(db)));
+        }
+
+        let aliases = load_aliases(&data_dir)?;
+
+        Ok(IndexRegistry { data_dir, saves: Mutex::new(()), aliases: RwLock::new(aliases), indexes: RwLock::new(indexes) })
+    }
+
+    // Finds an index by its name, or by an alias that points at only that index
+    fn get(&self, name: &str) -> Result<Arc<RwLock<Database>>, ApiError> {
+        self.get_with_name(name).map(|(_, db)| db)
+    }
+
+    // Like get, but also returns the name of the index that an alias points at
+    fn get_with_name(&self, name: &str) -> Result<NamedIndex, ApiError> {
+        let mut targets = self.resolve(name)?;
+        if targets.len() > 1 {
+            return Err(ServerError::AliasHasMultipleIndexes { alias: name.to_owned() }.into());
+        }
+
+        targets.pop().ok_or_else(|| ServerError::IndexNotFound { index: name.to_owned() }.into())
+    }
+
+    // Finds all of the indexes with the name, or that are pointed at by an alias with the name
+    fn resolve(&self, name: &str) -> Result<Vec<NamedIndex>, ApiError> {
+        let aliases = self.aliases.read()?;
+        let indexes = self.indexes.read()?;
+
+        if let Some(db) = indexes.get(name) {
+            return Ok(vec![(name.to_owned(), db.clone())]);
+        }
+
+        let targets = aliases.get(name).ok_or_else(|| ServerError::IndexNotFound { index: name.to_owned() })?;
+        let mut targets = targets.iter().filter_map(|index| indexes.get(index).map(|db| (index.clone(), db.clone()))).collect::<Vec<_>>();
+        targets.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(targets)
+    }
+
+    fn directory(&self, name: &str) -> PathBuf {
+        self.data_dir.join(name)
+    }
+
+    // Saves the index, unless it has been dropped since it was looked up
+    // Note: the index mustn't be locked by the caller
+    fn save(&self, name: &str, db: &Arc<RwLock<Database>>) -> Result<(), ApiError> {
+        let _saving = self.saves.lock()?;
+
+        let is_registered = self.indexes.read()?.get(name).is_some_and(|registered| Arc::ptr_eq(registered, db));
+        if is_registered {
+            persistence::save(&*db.read()?, &self.directory(name))?;
+        }
+
+        Ok(())
+    }
+}
+
+// Index names are used in URLs, so they're kept to lowercase letters, numbers, dashes and underscores
+fn is_valid_index_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+// The settings of a field. For example: {"type": "text", "boost": 2.0, "copy_to": ["all_text"], "stored": true}
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+struct FieldMappingSource {
+    #[serde(rename = "type", default)]
+    field_type: FieldType,
+    // Defaults to the keyword analyzer for keyword and hierarchy fields, and the standard analyzer for everything else
+    analyzer: Option<Analyzer>,
+    #[serde(default = "default_field_boost")]
+    boost: f32,
+    #[serde(default)]
+    copy_to: Vec<String>,
+    #[serde(default)]
+    stored: bool,
+}
+
+fn default_field_boost() -> f32 {
+    1.0
+}
+
+impl FieldMappingSource {
+    fn from_field_config(field_config: &FieldConfig, data_dict: &DataDictionary) -> FieldMappingSource {
+        let mut copy_to = field_config.copy_to.iter().filter_map(|field_id| data_dict.get_name(*field_id)).map(|name| name.to_owned()).collect::<Vec<_>>();
+        copy_to.sort();
+
+        FieldMappingSource {
+            field_type: field_config.field_type,
+            analyzer: Some(field_config.analyzer),
+            boost: field_config.boost,
+            copy_to,
+            stored: field_config.stored,
+        }
+    }
+
+    fn as_field_config(&self, data_dict: &DataDictionary) -> Result<FieldConfig, MappingError> {
+        let analyzer = self.analyzer.unwrap_or(match self.field_type {
+            FieldType::Keyword | FieldType::Hierarchy => Analyzer::Keyword,
+            _ => Analyzer::Standard,
+        });
+        let mut field_config = FieldConfig::default().field_type(self.field_type).analyzer(analyzer).boost(self.boost).stored(self.stored);
+
+        for copy_to in &self.copy_to {
+            match data_dict.get_by_name(copy_to) {
+                Some((field_id, _)) => field_config = field_config.copy_to(field_id),
+                None => return Err(MappingError::UnknownField { field: copy_to.clone() }),
+            }
+        }
+
+        Ok(field_config)
+    }
+}
+
+#[derive(Debug, Clone, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+struct MappingSource {
+    #[serde(default)]
+    fields: HashMap<String, FieldMappingSource>,
+}
+
+impl MappingSource {
+    fn from_data_dictionary(data_dict: &DataDictionary) -> MappingSource {
+        MappingSource {
+            fields: data_dict.field_names.iter().filter_map(|(name, field_id)| {
+                data_dict.get(*field_id).map(|field_config| (name.clone(), FieldMappingSource::from_field_config(field_config, data_dict)))
+            }).collect(),
+        }
+    }
+
+    // Adds the fields to the data dictionary, or updates them if they already exist
+    // Nothing is changed if any of the fields can't be applied
+    fn apply(&self, data_dict: &mut DataDictionary) -> Result<(), MappingError> {
+        let mut new_data_dict = data_dict.clone();
+
+        // Fields are added after the fields they copy into, so that those fields have ids
+        let mut remaining_fields = self.fields.iter().collect::<Vec<_>>();
+        remaining_fields.sort_by_key(|(name, _)| name.as_str());
+
+        while !remaining_fields.is_empty() {
+            let (ready_fields, waiting_fields): (Vec<_>, Vec<_>) = remaining_fields.into_iter().partition(|(_, field)| {
+                field.copy_to.iter().all(|copy_to| new_data_dict.field_names.contains_key(copy_to))
+            });
+
+            // If none of the fields are ready, they are either copying into a field that doesn't exist or into each other
+            if ready_fields.is_empty() {
+                let unknown_field = waiting_fields.iter().flat_map(|(_, field)| &field.copy_to).find(|copy_to| {
+                    !new_data_dict.field_names.contains_key(*copy_to) && !self.fields.contains_key(*copy_to)
+                });
+
+                return Err(match unknown_field {
+                    Some(field) => MappingError::UnknownField { field: field.clone() },
+                    None => MappingError::CopyToCycle { field: waiting_fields[0].0.clone() },
+                });
+            }
+
+            for (name, field) in ready_fields {
+                let field_config = field.as_field_config(&new_data_dict)?;
+                new_data_dict.put(name.clone(), field_config)?;
+            }
+
+            remaining_fields = waiting_fields;
+        }
+
+        *data_dict = new_data_dict;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, serde_derive::Deserialize)]
+struct CreateIndexRequest {
+    #[serde(default)]
+    mapping: MappingSource,
+    // Keep the source of each document so it can be returned with search results
+    #[serde(default)]
+    store_source: bool,
+}
+
+#[get("/")]
+fn index() -> &'static str {
+    "Hello, world!"
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct IndexSummary {
+    name: String,
+    documents: usize,
+}
+
+#[get("/indexes")]
+fn list_indexes(indexes: State<IndexRegistry>) -> Result<Json<Vec<IndexSummary>>, ApiError> {
+    let indexes = indexes.indexes.read()?;
+    let mut summaries = indexes.iter().map(|(name, db)| {
+        Ok(IndexSummary { name: name.clone(), documents: db.read()?.pk_to_id.len() })
+    }).collect::<Result<Vec<_>, ApiError>>()?;
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Json(summaries))
+}
+
+// The body is optional, an index with no fields is created without one
+#[put("/<index>", data = "<request>")]
+fn create_index(indexes: State<IndexRegistry>, index: String, request: Result<OptionalJsonBody<CreateIndexRequest>, ApiError>) -> Result<status::Custom<&'static str>, ApiError> {
+    let request = request?.0.unwrap_or_default();
+    if !is_valid_index_name(&index) {
+        return Err(ServerError::InvalidIndexName { index }.into());
+    }
+
+    let directory = indexes.directory(&index);
+    let _saving = indexes.saves.lock()?;
+    let aliases = indexes.aliases.read()?;
+    let mut indexes = indexes.indexes.write()?;
+    if indexes.contains_key(&index) || aliases.contains_key(&index) {
+        return Err(ServerError::IndexAlreadyExists { index }.into());
+    }
+
+    let mut db = Database::default();
+    db.store_source = request.store_source;
+    request.mapping.apply(&mut db.data_dictionary)?;
+
+    // Save the empty index straight away so it is still there after a restart
+    persistence::save(&db, &directory)?;
+    indexes.insert(index, Arc::new(RwLock::new(db)));
+
+    Ok(status::Custom(Status::Created, "Created"))
+}
+
+#[get("/<index>/mapping")]
+fn get_mapping(indexes: State<IndexRegistry>, index: String) -> Result<Json<MappingSource>, ApiError> {
+    let db = indexes.get(&index)?;
+    let db = db.read()?;
+
+    Ok(Json(MappingSource::from_data_dictionary(&db.data_dictionary)))
+}
+
+// Adds new fields to the index. Existing fields can be included, but only if they haven't been changed in a way that would affect documents that are already indexed
+#[put("/<index>/mapping", format = "application/json", data = "<mapping>")]
+fn put_mapping(indexes: State<IndexRegistry>, index: String, mapping: Result<JsonBody<MappingSource>, ApiError>) -> Result<Json<MappingSource>, ApiError> {
+    let mapping = mapping?.0;
+    let (name, db) = indexes.get_with_name(&index)?;
+    let response = {
+        let mut db = db.write()?;
+        mapping.apply(&mut db.data_dictionary)?;
+        MappingSource::from_data_dictionary(&db.data_dictionary)
+    };
+
+    // The mapping is saved straight away, as documents can't be reindexed if it is lost
+    indexes.save(&name, &db)?;
+
+    Ok(Json(response))
+}
+
+#[delete("/<index>")]
+fn drop_index(indexes: State<IndexRegistry>, index: String) -> Result<&'static str, ApiError> {
+    let _saving = indexes.saves.lock()?;
+    let mut aliases = indexes.aliases.write()?;
+    let mut all_indexes = indexes.indexes.write()?;
+    if !all_indexes.contains_key(&index) {
+        return Err(ServerError::IndexNotFound { index }.into());
+    }
+
+    // Stop any aliases from pointing at the index, so they don't pick up a new index with the same name
+    let mut new_aliases = aliases.clone();
+    for targets in new_aliases.values_mut() {
+        targets.retain(|target| *target != index);
+    }
+    new_aliases.retain(|_, targets| !targets.is_empty());
+    if new_aliases != *aliases {
+        save_aliases(&indexes.data_dir, &new_aliases)?;
+        *aliases = new_aliases;
+    }
+
+    // The index is only removed once its directory has gone, otherwise it would be loaded again on restart
+    // The directory doesn't exist if the index has never been saved
+    match fs::remove_dir_all(indexes.directory(&index)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+        _ => {}
+    }
+    all_indexes.remove(&index);
+
+    Ok("Dropped")
+}
+
+#[get("/aliases")]
+fn list_aliases(indexes: State<IndexRegistry>) -> Result<Json<BTreeMap<String, Vec<String>>>, ApiError> {
+    let aliases = indexes.aliases.read()?;
+
+    Ok(Json(aliases.iter().map(|(alias, targets)| {
+        let mut targets = targets.clone();
+        targets.sort();
+        (alias.clone(), targets)
+    }).collect()))
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+struct AliasActionTarget {
+    index: String,
+    alias: String,
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AliasAction {
+    Add(AliasActionTarget),
+    Remove(AliasActionTarget),
+}
+
+// For example: {"actions": [{"remove": {"index": "pages_v1", "alias": "pages"}}, {"add": {"index": "pages_v2", "alias": "pages"}}]}
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+struct UpdateAliasesRequest {
+    actions: Vec<AliasAction>,
+}
+
+// Applies all of the actions at once, so searches never see an alias that is only partly updated
+// If any action is invalid, none of them are applied
+#[post("/aliases", format = "application/json", data = "<request>")]
+fn update_aliases(indexes: State<IndexRegistry>, request: Result<JsonBody<UpdateAliasesRequest>, ApiError>) -> Result<&'static str, ApiError> {
+    let request = request?.0;
+    let mut aliases = indexes.aliases.write()?;
+    let mut new_aliases = aliases.clone();
+
+    {
+        let existing_indexes = indexes.indexes.read()?;
+
+        for action in request.actions {
+            match action {
+                AliasAction::Add(AliasActionTarget { index, alias }) => {
+                    if !existing_indexes.contains_key(&index) {
+                        return Err(ServerError::IndexNotFound { index }.into());
+                    }
+                    if !is_valid_index_name(&alias) {
+                        return Err(ServerError::InvalidAliasName { alias }.into());
+                    }
+                    if existing_indexes.contains_key(&alias) {
+                        return Err(ServerError::IndexAlreadyExists { index: alias }.into());
+                    }
+
+                    let targets = new_aliases.entry(alias).or_default();
+                    if !targets.contains(&index) {
+                        targets.push(index);
+                    }
+                }
+                AliasAction::Remove(AliasActionTarget { index, alias }) => {
+                    let targets = match new_aliases.get_mut(&alias) {
+                        Some(targets) if targets.contains(&index) => targets,
+                        _ => return Err(ServerError::AliasNotFound { alias, index }.into()),
+                    };
+
+                    targets.retain(|target| *target != index);
+                    if targets.is_empty() {
+                        new_aliases.remove(&alias);
+                    }
+                }
+            }
+        }
+    }
+
+    save_aliases(&indexes.data_dir, &new_aliases)?;
+    *aliases = new_aliases;
+
+    Ok("Updated")
+}
+
+// Writes the index to its directory in the data directory. Flushing an alias flushes all of the indexes it points at
+// Creating an index, changing its mapping and resetting it are saved straight away. Changes to documents
+// (insert, bulk and the deletes) are only kept in memory until the index is flushed, and are lost on restart otherwise
+#[post("/<index>/flush")]
+fn flush(indexes: State<IndexRegistry>, index: String) -> Result<&'static str, ApiError> {
+    for (name, db) in indexes.resolve(&index)? {
+        indexes.save(&name, &db)?;
+    }
+
+    Ok("Flushed")
+}
+
+#[get("/<index>/stats")]
+fn stats(indexes: State<IndexRegistry>, index: String) -> Result<Json<DatabaseStats>, ApiError> {
+    let db = indexes.get(&index)?;
+    let db = db.read()?;
+
+    Ok(Json(db.stats()))
+}
+
+// Deletes all of the documents in the index, but keeps its fields
+#[post("/<index>/reset")]
+fn reset(indexes: State<IndexRegistry>, index: String) -> Result<&'static str, ApiError> {
+    let (name, db) = indexes.get_with_name(&index)?;
+    db.write()?.reset();
+    indexes.save(&name, &db)?;
+
+    Ok("Reset")
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct InsertResponse {
+    id: DocumentId,
+    pk: String,
+    created: bool,
+}
+
+// Validates and indexes the document, replacing the document with the same PK if there is one
+fn index_document(db: &mut Database, doc: &DocumentSource) -> Result<(DocumentId, bool), ApiError> {
+    doc.validate(&db.data_dictionary)?;
+
+    let mut document = doc.as_document(&mut db.term_dictionary, &db.data_dictionary);
+    if db.store_source {
+        document.source = serde_json::to_value(doc).ok();
+    }
+
+    Ok(db.upsert_document(doc.pk.clone(), document))
+}
+
+// Indexes a single document, replacing the document with the same PK if there is one
+#[post("/<index>/insert", format = "application/json", data = "<doc>")]
+fn insert(indexes: State<IndexRegistry>, index: String, doc: Result<JsonBody<DocumentSource>, ApiError>) -> Result<status::Custom<Json<InsertResponse>>, ApiError> {
+    let doc = doc?.0;
+    let db = indexes.get(&index)?;
+    let (id, created) = index_document(&mut *db.write()?, &doc)?;
+
+    let status = if created { Status::Created } else { Status::Ok };
+    Ok(status::Custom(status, Json(InsertResponse { id, pk: doc.pk, created })))
+}
+
+// Each line of a bulk request is one of these. For example: {"delete": {"pk": "1"}}
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum BulkAction {
+    // Indexes the document, replacing the document with the same PK if there is one
+    Index(DocumentSource),
+    // Replaces the given fields of an existing document and keeps the others
+    Update(DocumentSource),
+    Delete {
+        pk: String,
+    },
+}
+
+impl BulkAction {
+    fn name(&self) -> &'static str {
+        match self {
+            BulkAction::Index(_) => "index",
+            BulkAction::Update(_) => "update",
+            BulkAction::Delete { .. } => "delete",
+        }
+    }
+
+    fn pk(&self) -> &str {
+        match self {
+            BulkAction::Index(doc) | BulkAction::Update(doc) => &doc.pk,
+            BulkAction::Delete { pk } => pk,
+        }
+    }
+
+    fn apply(&self, db: &mut Database) -> Result<(DocumentId, Status), ApiError> {
+        match self {
+            BulkAction::Index(doc) => {
+                let (id, created) = index_document(db, doc)?;
+                Ok((id, if created { Status::Created } else { Status::Ok }))
+            }
+            BulkAction::Update(update) => {
+                let (_, existing) = db.get_document_by_pk(&update.pk).ok_or_else(|| ServerError::DocumentNotFound { pk: update.pk.clone() })?;
+                let source = existing.source.clone().ok_or_else(|| ServerError::SourceNotStored { pk: update.pk.clone() })?;
+                let mut doc: DocumentSource = serde_json::from_value(source).map_err(|_| ServerError::Internal)?;
+                doc.fields.extend(update.fields.clone());
+
+                let (id, _) = index_document(db, &doc)?;
+                Ok((id, Status::Ok))
+            }
+            BulkAction::Delete { pk } => {
+                let id = db.delete_document_by_pk(pk).ok_or_else(|| ServerError::DocumentNotFound { pk: pk.clone() })?;
+                Ok((id, Status::Ok))
+            }
+        }
+    }
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct BulkItemResponse {
+    // Starts at 1
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pk: Option<String>,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<DocumentId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ApiError>,
+}
+
+impl BulkItemResponse {
+    fn new(line: usize, action: Option<&BulkAction>, result: Result<(DocumentId, Status), ApiError>) -> BulkItemResponse {
+        let (status, id, error) = match result {
+            Ok((id, status)) => (status, Some(id), None),
+            Err(error) => (error.status(), None, Some(error)),
+        };
+
+        BulkItemResponse {
+            line,
+            action: action.map(BulkAction::name),
+            pk: action.map(|action| action.pk().to_owned()),
+            status: status.code,
+            id,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct BulkResponse {
+    // True if any of the items failed
+    errors: bool,
+    items: Vec<BulkItemResponse>,
+}
+
+// The number of lines that are applied each time the write lock is taken
+const BULK_CHUNK_SIZE: usize = 500;
+
+// Applies a stream of actions, one per line. Items that fail are reported in the response and don't stop the others
+// The lines are read in chunks and the lock is released between them, so that searches aren't blocked for the whole request
+#[post("/<index>/bulk", format = "application/x-ndjson", data = "<body>")]
+fn bulk(indexes: State<IndexRegistry>, index: String, mut body: NdjsonBody) -> Result<Json<BulkResponse>, ApiError> {
+    let db = indexes.get(&index)?;
+    let mut items = Vec::new();
+    let mut line_number = 0;
+    let mut finished = false;
+
+    while !finished {
+        // Read the chunk before taking the lock, so a slow client doesn't hold it
+        let mut chunk = Vec::with_capacity(BULK_CHUNK_SIZE);
+        while chunk.len() < BULK_CHUNK_SIZE {
+            let line = match body.read_line() {
+                Ok(NdjsonLine::Line(line)) => line,
+                Ok(NdjsonLine::TooLarge) => {
+                    line_number += 1;
+                    chunk.push((line_number, Err(ServerError::PayloadTooLarge { limit: body.line_limit }.into())));
+                    continue;
+                }
+                Ok(NdjsonLine::End) => {
+                    finished = true;
+                    break;
+                }
+                // The rest of the body can't be read, so report it on the next line and stop
+                Err(error) => {
+                    chunk.push((line_number + 1, Err(ServerError::InvalidJson { message: error.to_string() }.into())));
+                    finished = true;
+                    break;
+                }
+            };
+
+            line_number += 1;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            let action = serde_json::from_slice::<BulkAction>(&line).map_err(|error| ServerError::InvalidJson { message: error.to_string() }.into());
+            chunk.push((line_number, action));
+        }
+
+        if chunk.is_empty() {
+            continue;
+        }
+
+        let mut db = db.write()?;
+        for (line, action) in chunk {
+            items.push(match action {
+                Ok(action) => {
+                    let result = action.apply(&mut db);
+                    BulkItemResponse::new(line, Some(&action), result)
+                }
+                Err(error) => BulkItemResponse::new(line, None, Err(error)),
+            });
+        }
+    }
+
+    Ok(Json(BulkResponse { errors: items.iter().any(|item| item.error.is_some()), items }))
+}
+
+// Sorts by the value of a field, or by score if the field is "_score"
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+struct SortSource {
+    field: String,
+    // Defaults to descending for score and ascending for fields
+    order: Option<SortOrder>,
+    #[serde(default)]
+    missing: MissingOrder,
+}
+
+impl SortSource {
+    fn as_sort(&self, data_dict: &DataDictionary) -> Result<Sort, ApiError> {
+        let key = if self.field == "_score" {
+            SortKey::Score
+        } else {
+            match data_dict.get_by_name(&self.field) {
+                Some((field_id, field_config)) if field_config.field_type.is_sortable() => SortKey::Field(field_id),
+                Some((_, field_config)) => return Err(ServerError::FieldNotSortable { field: self.field.clone(), field_type: field_config.field_type }.into()),
+                None => return Err(sparrow::Error::UnknownField { field: self.field.clone() }.into()),
+            }
+        };
+
+        let order = self.order.unwrap_or(if key == SortKey::Score { SortOrder::Desc } else { SortOrder::Asc });
+
+        Ok(Sort { key, order, missing: self.missing })
+    }
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+struct RangeBucketSource {
+    key: Option<String>,
+    from: Option<RangeBoundSource>,
+    to: Option<RangeBoundSource>,
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+enum AggregationSource {
+    Terms {
+        field: String,
+        #[serde(default = "default_terms_size")]
+        size: usize,
+        #[serde(default)]
+        aggregations: HashMap<String, AggregationSource>,
+    },
+    Histogram {
+        field: String,
+        interval: f64,
+        #[serde(default)]
+        aggregations: HashMap<String, AggregationSource>,
+    },
+    DateHistogram {
+        field: String,
+        interval: DateUnit,
+        // A UTC offset, for example: "+01:00"
+        #[serde(default)]
+        time_zone: String,
+        #[serde(default)]
+        aggregations: HashMap<String, AggregationSource>,
+    },
+    Range {
+        field: String,
+        ranges: Vec<RangeBucketSource>,
+        #[serde(default)]
+        aggregations: HashMap<String, AggregationSource>,
+    },
+    Stats {
+        field: String,
+    },
+}
+
+fn default_terms_size() -> usize {
+    10
+}
+
+// Note: aggregations on fields that don't exist or have the wrong type are ignored, as the field might only exist in some of the indexes
+// Settings that are invalid, such as range bounds that can't be parsed, are reported as errors
+fn as_aggregations(aggregations: &HashMap<String, AggregationSource>, data_dict: &DataDictionary) -> Result<HashMap<String, Aggregation>, ApiError> {
+    let mut result = HashMap::new();
+
+    for (name, aggregation) in aggregations {
+        if let Some(aggregation) = aggregation.as_aggregation(name, data_dict)? {
+            result.insert(name.clone(), aggregation);
+        }
+    }
+
+    Ok(result)
+}
+
+impl AggregationSource {
+    fn field(&self) -> &str {
+        match self {
+            AggregationSource::Terms { field, .. } | AggregationSource::Histogram { field, .. } | AggregationSource::DateHistogram { field, .. } | AggregationSource::Range { field, .. } | AggregationSource::Stats { field } => field,
+        }
+    }
+
+    fn as_aggregation(&self, name: &str, data_dict: &DataDictionary) -> Result<Option<Aggregation>, ApiError> {
+        let invalid = |message: String| ApiError::from(ServerError::InvalidAggregation { aggregation: name.to_owned(), message });
+
+        match self {
+            AggregationSource::Histogram { interval, .. } if !interval.is_finite() || *interval <= 0.0 => {
+                return Err(invalid(format!("interval must be a positive number, got {}", interval)));
+            }
+            AggregationSource::DateHistogram { time_zone, .. } if date::parse_offset(time_zone).is_none() => {
+                return Err(invalid(format!("invalid time_zone '{}'", time_zone)));
+            }
+            _ => {}
+        }
+
+        let (field_id, field_config) = match data_dict.get_by_name(self.field()) {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+        let field_type = field_config.field_type;
+
+        Ok(match self {
+            AggregationSource::Terms { size, aggregations, .. } => {
+                match field_type {
+                    FieldType::Keyword | FieldType::Boolean | FieldType::Hierarchy => Some(Aggregation::Terms { field: field_id, size: *size, aggregations: as_aggregations(aggregations, data_dict)? }),
+                    _ => None,
+                }
+            }
+            AggregationSource::Histogram { interval, aggregations, .. } => {
+                if !field_type.is_numeric() {
+                    return Ok(None);
+                }
+
+                Some(Aggregation::Histogram { field: field_id, interval: *interval, aggregations: as_aggregations(aggregations, data_dict)? })
+            }
+            AggregationSource::DateHistogram { interval, time_zone, aggregations, .. } => {
+                if field_type != FieldType::Date {
+                    return Ok(None);
+                }
+
+                let offset = date::parse_offset(time_zone).ok_or_else(|| invalid(format!("invalid time_zone '{}'", time_zone)))?;
+                Some(Aggregation::DateHistogram { field: field_id, interval: *interval, offset, aggregations: as_aggregations(aggregations, data_dict)? })
+            }
+            AggregationSource::Range { ranges, aggregations, .. } => {
+                if !field_type.is_numeric() && field_type != FieldType::Date {
+                    return Ok(None);
+                }
+
+                // A bound that can't be parsed would otherwise leave that end of the bucket open
+                let bound = |bound: &Option<RangeBoundSource>, bound_name: &str| match bound {
+                    Some(bound) => bound.as_value(field_type, false).map(|bound| Some(bound.as_f64())).ok_or_else(|| invalid(format!("the {} bound isn't a valid {}", bound_name, if field_type == FieldType::Date { "date" } else { "number" }))),
+                    None => Ok(None),
+                };
+
+                let ranges = ranges.iter().map(|range| Ok(RangeBucket {
+                    key: range.key.clone(),
+                    from: bound(&range.from, "from")?,
+                    to: bound(&range.to, "to")?,
+                })).collect::<Result<Vec<_>, ApiError>>()?;
+
+                Some(Aggregation::Range { field: field_id, ranges, aggregations: as_aggregations(aggregations, data_dict)? })
+            }
+            AggregationSource::Stats { .. } => {
+                if !field_type.is_numeric() && field_type != FieldType::Date {
+                    return Ok(None);
+                }
+
+                Some(Aggregation::Stats { field: field_id })
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+struct SearchRequest {
+    query: QuerySource,
+    #[serde(default)]
+    sort: Vec<SortSource>,
+    #[serde(default)]
+    aggregations: HashMap<String, AggregationSource>,
+    // The names of the stored fields to return with each hit
+    #[serde(default)]
+    stored_fields: Vec<String>,
+    // Return the source of each hit, if the database is keeping them
+    #[serde(default)]
+    source: bool,
+    highlight: Option<HighlightSource>,
+    suggest: Option<SuggestSource>,
+    // Refuse to run the query if it has any errors, such as fields that don't exist, rather than letting those parts match nothing
+    #[serde(default)]
+    strict: bool,
+}
+
+// Suggests a correction of the text if the query doesn't find anything
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+struct SuggestSource {
+    text: String,
+    field: String,
+}
+
+impl SuggestSource {
+    fn suggest(&self, db: &Database) -> Option<String> {
+        let (field_id, field_config) = db.data_dictionary.get_by_name(&self.field)?;
+        let tokens = field_config.analyzer.analyze(&self.text);
+        let terms = tokens.iter().map(|token| token.term.as_str()).collect::<Vec<_>>();
+
+        suggest::suggest_phrase(db, field_id, &terms).map(|terms| terms.join(" "))
+    }
+}
+
+// Highlights the matches in stored text fields
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+struct HighlightSource {
+    fields: Vec<String>,
+    pre_tag: Option<String>,
+    post_tag: Option<String>,
+    number_of_fragments: Option<usize>,
+    fragment_size: Option<usize>,
+}
+
+impl HighlightSource {
+    fn as_options(&self) -> HighlightOptions {
+        let defaults = HighlightOptions::default();
+
+        HighlightOptions {
+            pre_tag: self.pre_tag.clone().unwrap_or(defaults.pre_tag),
+            post_tag: self.post_tag.clone().unwrap_or(defaults.post_tag),
+            number_of_fragments: self.number_of_fragments.unwrap_or(defaults.number_of_fragments),
+            fragment_size: self.fragment_size.unwrap_or(defaults.fragment_size),
+        }
+    }
+}
+
+// For backwards compatibility, the search endpoint also accepts a bare query
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+#[serde(untagged)]
+enum SearchRequestSource {
+    Request(Box<SearchRequest>),
+    Query(QuerySource),
+}
+
+impl SearchRequestSource {
+    fn into_request(self) -> SearchRequest {
+        match self {
+            SearchRequestSource::Request(request) => *request,
+            SearchRequestSource::Query(query) => SearchRequest { query, sort: Vec::new(), aggregations: HashMap::new(), stored_fields: Vec::new(), source: false, highlight: None, suggest: None, strict: false },
+        }
+    }
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct SearchResult {
+    // Only included when the search is on an alias that points at more than one index
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<String>,
+    pk: String,
+    score: f32,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    fields: HashMap<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    highlight: HashMap<String, Vec<String>>,
+}
+
+// Finds the values of the given stored fields in a document. Fields that aren't stored are left out
+fn get_stored_fields(doc: &Document, field_names: &[String], data_dict: &DataDictionary) -> HashMap<String, serde_json::Value> {
+    field_names.iter().filter_map(|field_name| {
+        let field_id = data_dict.field_names.get(field_name)?;
+        doc.stored_fields.get(field_id).map(|value| (field_name.clone(), value.clone()))
+    }).collect()
+}
+
+// Highlights the matches of the query in each field. The fields must be stored text fields
+fn get_highlights(doc: &Document, query: &Query, field_names: &[String], options: &HighlightOptions, data_dict: &DataDictionary) -> HashMap<String, Vec<String>> {
+    field_names.iter().filter_map(|field_name| {
+        let field_id = data_dict.field_names.get(field_name)?;
+        let text = doc.stored_fields.get(field_id)?.as_str()?;
+        let tsvector = doc.fields.get(field_id)?;
+        let offsets = doc.offsets.get(field_id)?;
+
+        let fragments = highlight::highlight(text, offsets, &highlight::matched_positions(query, *field_id, tsvector), options);
+        if fragments.is_empty() {
+            return None;
+        }
+
+        Some((field_name.clone(), fragments))
+    }).collect()
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct SearchResponse {
+    hits: Vec<SearchResult>,
+    aggregations: HashMap<String, AggregationResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestion: Option<String>,
+}
+
+// Bare queries get a list of results back, like they did before search requests were added
+#[derive(Debug, serde_derive::Serialize)]
+#[serde(untagged)]
+enum SearchResponseBody {
+    Response(SearchResponse),
+    Hits(Vec<SearchResult>),
+}
+
+// Searching an alias that points at several indexes runs the search on each of them and merges the results
+#[post("/<index>/search", format = "application/json", data = "<request>")]
+fn search(indexes: State<IndexRegistry>, index: String, request: Result<JsonBody<SearchRequestSource>, ApiError>) -> Result<Json<SearchResponseBody>, ApiError> {
+    let request = request?.0;
+    let targets = indexes.resolve(&index)?;
+    let dbs = targets.iter().map(|(_, db)| db.read()).collect::<Result<Vec<_>, _>>()?;
+    let is_bare_query = match request {
+        SearchRequestSource::Query(_) => true,
+        SearchRequestSource::Request(_) => false,
+    };
+    let request = request.into_request();
+
+    // When searching several indexes, the field must be sortable in all of them so the results can be merged
+    let sorts = dbs.iter().map(|db| request.sort.iter().map(|sort| sort.as_sort(&db.data_dictionary)).collect::<Result<Vec<_>, _>>()).collect::<Result<Vec<_>, _>>()?;
+    let highlight_options = request.highlight.as_ref().map(|highlight| highlight.as_options());
+
+    let mut queries = Vec::new();
+    let mut documents = Vec::new();
+    let mut aggregations = HashMap::new();
+
+    for (i, db) in dbs.iter().enumerate() {
+        if request.strict {
+            request.query.check(db)?;
+        }
+
+        let query = request.query.as_query(db);
+        query.validate()?;
+        let results = db.search_with_sort_values(&query, &sorts[i]);
+
+        let document_ids = results.iter().map(|(document_id, _, _)| *document_id).collect::<Vec<_>>();
+        let db_aggregations = as_aggregations(&request.aggregations, &db.data_dictionary)?;
+        aggregations = aggregation::merge_all(&db_aggregations, aggregations, aggregation::run_all(&db_aggregations, db, &document_ids));
+
+        documents.extend(results.into_iter().map(|(document_id, score, values)| (i, document_id, score, values)));
+        queries.push(query);
+    }
+
+    if dbs.len() > 1 {
+        // The sorts only differ between indexes by the ids of their fields, so any of them can be used to compare the values
+        let default_sort = [Sort::score()];
+        let merge_sort = sorts.first().filter(|sort| !sort.is_empty()).map(|sort| &sort[..]).unwrap_or(&default_sort[..]);
+        documents.sort_by(|a, b| sort::compare_all(&a.3, &b.3, merge_sort).then_with(|| a.0.cmp(&b.0)).then_with(|| (a.1).0.cmp(&(b.1).0)));
+    }
+
+    let hits = documents.into_iter().filter_map(|(i, document_id, score, _)| {
+        let db = &dbs[i];
+        let doc = db.get_document(document_id).ok()?;
+
+        Some(SearchResult {
+            index: if dbs.len() > 1 { Some(targets[i].0.clone()) } else { None },
+            pk: db.get_pk(document_id).ok()?.to_owned(),
+            score,
+            fields: get_stored_fields(doc, &request.stored_fields, &db.data_dictionary),
+            source: if request.source { doc.source.clone() } else { None },
+            highlight: match (&request.highlight, &highlight_options) {
+                (Some(highlight), Some(options)) => get_highlights(doc, &queries[i], &highlight.fields, options, &db.data_dictionary),
+                _ => HashMap::new(),
+            },
+        })
+    }).collect::<Vec<SearchResult>>();
+
+    if is_bare_query {
+        Ok(Json(SearchResponseBody::Hits(hits)))
+    } else {
+        let suggestion = match &request.suggest {
+            Some(suggest) if hits.is_empty() => dbs.iter().filter_map(|db| suggest.suggest(db)).next(),
+            _ => None,
+        };
+
+        Ok(Json(SearchResponseBody::Response(SearchResponse { hits, aggregations, suggestion })))
+    }
+}
+
+// A query with filters that don't affect scoring. For example: {"filters": [{"Term": {"field": "live", "term": "true"}}]}
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CountRequest {
+    // Defaults to matching all documents, so that only the filters are applied
+    query: Option<QuerySource>,
+    #[serde(default)]
+    filters: Vec<QuerySource>,
+}
+
+// The count endpoint also accepts a bare query
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+#[serde(untagged)]
+enum CountRequestSource {
+    Request(CountRequest),
+    Query(QuerySource),
+}
+
+impl CountRequestSource {
+    fn as_query(&self, db: &Database) -> Query {
+        match self {
+            CountRequestSource::Query(query) => query.as_query(db),
+            CountRequestSource::Request(request) => {
+                let query = request.query.as_ref().map(|query| query.as_query(db)).unwrap_or_else(Query::match_all);
+
+                if request.filters.is_empty() {
+                    query
+                } else {
+                    Query::filter(query, Query::and(request.filters.iter().map(|filter| filter.as_query(db)).collect()))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct CountResponse {
+    count: usize,
+}
+
+// Counts the documents that match a query. This is cheaper than searching as nothing is scored or sorted
+#[post("/<index>/count", format = "application/json", data = "<request>")]
+fn count(indexes: State<IndexRegistry>, index: String, request: Result<JsonBody<CountRequestSource>, ApiError>) -> Result<Json<CountResponse>, ApiError> {
+    let request = request?.0;
+    let mut count = 0;
+
+    for (_, db) in indexes.resolve(&index)? {
+        let db = db.read()?;
+        let query = request.as_query(&db);
+        query.validate()?;
+        count += db.count(&query);
+    }
+
+    Ok(Json(CountResponse { count }))
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct ValidateResponse {
+    // True if none of the problems are errors
+    valid: bool,
+    problems: Vec<QueryProblem>,
+}
+
+// Reports the problems with a query without running it
+#[post("/<index>/validate", format = "application/json", data = "<query>")]
+fn validate(indexes: State<IndexRegistry>, index: String, query: Result<JsonBody<QuerySource>, ApiError>) -> Result<Json<ValidateResponse>, ApiError> {
+    let query = query?.0;
+    let targets = indexes.resolve(&index)?;
+    let mut problems = Vec::new();
+
+    for (name, db) in &targets {
+        let db = db.read()?;
+        problems.extend(query.validate(&db).into_iter().map(|mut problem| {
+            if targets.len() > 1 {
+                problem.index = Some(name.clone());
+            }
+
+            problem
+        }));
+    }
+
+    let valid = problems.iter().all(|problem| problem.severity != Severity::Error);
+    Ok(Json(ValidateResponse { valid, problems }))
+}
+
+#[derive(Debug, Clone, serde_derive::Deserialize)]
+struct CompleteRequest {
+    field: String,
+    prefix: String,
+    #[serde(default = "default_complete_size")]
+    size: usize,
+    // The number of typos allowed in the prefix
+    #[serde(default)]
+    fuzziness: usize,
+}
+
+fn default_complete_size() -> usize {
+    5
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct CompletionResult {
+    text: String,
+    pk: String,
+    weight: f32,
+}
+
+#[post("/<index>/complete", format = "application/json", data = "<request>")]
+fn complete(indexes: State<IndexRegistry>, index: String, request: Result<JsonBody<CompleteRequest>, ApiError>) -> Result<Json<Vec<CompletionResult>>, ApiError> {
+    let request = request?.0;
+    let db = indexes.get(&index)?;
+    let db = db.read()?;
+
+    let (field_id, _) = db.data_dictionary.get_by_name(&request.field).ok_or_else(|| sparrow::Error::UnknownField { field: request.field.clone() })?;
+    let completions = db.complete(field_id, &request.prefix, request.size, request.fuzziness);
+
+    Ok(Json(completions.into_iter().filter_map(|entry| Some(CompletionResult {
+        text: entry.input.clone(),
+        pk: db.get_pk(entry.document_id).ok()?.to_owned(),
+        weight: entry.weight,
+    })).collect()))
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct DocumentResponse {
+    pk: String,
+    fields: HashMap<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<serde_json::Value>,
+}
+
+// Returns all of the stored fields of a document, and its source if it was kept
+#[get("/<index>/doc/<pk>")]
+fn get_document(indexes: State<IndexRegistry>, index: String, pk: String) -> Result<Json<DocumentResponse>, ApiError> {
+    let db = indexes.get(&index)?;
+    let db = db.read()?;
+    let (_, doc) = db.get_document_by_pk(&pk).ok_or_else(|| ServerError::DocumentNotFound { pk: pk.clone() })?;
+
+    let fields = doc.stored_fields.iter().filter_map(|(field_id, value)| {
+        db.data_dictionary.get_name(*field_id).map(|field_name| (field_name.to_owned(), value.clone()))
+    }).collect();
+
+    Ok(Json(DocumentResponse { pk, fields, source: doc.source.clone() }))
+}
+
+#[delete("/<index>/doc/<pk>")]
+fn delete_document(indexes: State<IndexRegistry>, index: String, pk: String) -> Result<&'static str, ApiError> {
+    let db = indexes.get(&index)?;
+    let mut db = db.write()?;
+    db.delete_document_by_pk(&pk).ok_or(ServerError::DocumentNotFound { pk })?;
+
+    Ok("Deleted")
+}
+
+#[derive(Debug, serde_derive::Serialize)]
+struct DeleteByQueryResponse {
+    deleted: usize,
+}
+
+#[post("/<index>/delete_by_query", format = "application/json", data = "<query>")]
+fn delete_by_query(indexes: State<IndexRegistry>, index: String, query: Result<JsonBody<QuerySource>, ApiError>) -> Result<Json<DeleteByQueryResponse>, ApiError> {
+    let query = query?.0;
+    let db = indexes.get(&index)?;
+    let mut db = db.write()?;
+
+    // Unknown fields can turn parts of the query into MatchAll (for example, Missing on a field that doesn't exist)
+    // so queries with errors are always rejected here rather than risking deleting the whole index
+    query.check(&db)?;
+    let query = query.as_query(&db);
+    query.validate()?;
+
+    Ok(Json(DeleteByQueryResponse { deleted: db.delete_by_query(&query) }))
+}
+
+// Errors that Rocket handles itself, such as requests for routes that don't exist, are returned as JSON too
+#[catch(400)]
+fn bad_request() -> ApiError {
+    ServerError::BadRequest.into()
+}
+
+#[catch(404)]
+fn not_found() -> ApiError {
+    ServerError::NotFound.into()
+}
+
+#[catch(413)]
+fn payload_too_large(request: &Request) -> ApiError {
+    ServerError::PayloadTooLarge { limit: request.limits().get("json").unwrap_or(DEFAULT_JSON_LIMIT) }.into()
+}
+
+#[catch(422)]
+fn unprocessable_entity() -> ApiError {
+    ServerError::UnprocessableEntity.into()
+}
+
+#[catch(500)]
+fn internal_error() -> ApiError {
+    ServerError::Internal.into()
+}
+
+fn main() {
+    let data_dir = env::var("SPARROW_DATA_DIR").unwrap_or_else(|_| "./data".to_owned());
+    let indexes = IndexRegistry::open(PathBuf::from(&data_dir)).unwrap_or_else(|error| {
+        eprintln!("Cannot start with the data in {}: {}", data_dir, error);
+        std::process::exit(1);
+    });
+
+    rocket::ignite()
+        .manage(indexes)
+        .mount("/", routes![index, list_indexes, list_aliases, update_aliases, create_index, drop_index, flush, stats, get_mapping, put_mapping, reset, insert, bulk, search, count, validate, complete, get_document, delete_document, delete_by_query])
+        .register(catchers![bad_request, not_found, payload_too_large, unprocessable_entity, internal_error])
+        .launch();
+}
+
+#[cfg(test)]
+mod tests {
+    use sparrow::Database;
+    use sparrow::analysis::Token;
+    use sparrow::data_dictionary::{FieldConfig, FieldType, DataDictionary, MappingError};
+    use sparrow::query::Query;
+    use super::{tokens_to_text, index_document, QuerySource, MappingSource, IndexRegistry, OpenError, ALIASES_FILENAME};
+
+    fn token(term: &str, position: usize, offsets: Option<(usize, usize)>) -> Token {
+        Token { term: term.to_owned(), position, offsets }
+    }
+
+    fn make_database() -> Database {
+        let mut db = Database::default();
+        db.data_dictionary.insert("title".to_owned(), FieldConfig::default());
+        db.data_dictionary.insert("tags".to_owned(), FieldConfig::default().field_type(FieldType::Keyword));
+        db.data_dictionary.insert("rating".to_owned(), FieldConfig::default().field_type(FieldType::Integer));
+
+        let doc = serde_json::from_str(r#"{"pk": "1", "fields": {"title": "Hello world", "tags": ["red"], "rating": 5}}"#).unwrap();
+        index_document(&mut db, &doc).unwrap();
+        db
+    }
+
+    fn query(json: &str) -> QuerySource {
+        serde_json::from_str(json).unwrap()
+    }
+
+    // The problems with the query, as they would be returned by /validate
+    fn problems(db: &Database, json: &str) -> serde_json::Value {
+        serde_json::to_value(query(json).validate(db)).unwrap()
+    }
+
+    fn apply_mapping(json: &str) -> Result<DataDictionary, MappingError> {
+        let mapping: MappingSource = serde_json::from_str(json).unwrap();
+        let mut data_dict = DataDictionary::default();
+        mapping.apply(&mut data_dict).map(|_| data_dict)
+    }
+
+    #[test]
+    fn test_apply_mapping() {
+        let data_dict = apply_mapping(r#"{"fields": {"title": {"copy_to": ["all_text"]}, "all_text": {}}}"#).unwrap();
+        assert!(data_dict.get_by_name("title").is_some());
+
+        assert_eq!(apply_mapping(r#"{"fields": {"title": {"copy_to": ["missing"]}}}"#).unwrap_err(), MappingError::UnknownField { field: "missing".to_owned() });
+        assert_eq!(apply_mapping(r#"{"fields": {"title": {"copy_to": ["title"]}}}"#).unwrap_err(), MappingError::CopyToCycle { field: "title".to_owned() });
+        assert_eq!(apply_mapping(r#"{"fields": {"a": {"copy_to": ["b"]}, "b": {"copy_to": ["a"]}}}"#).unwrap_err(), MappingError::CopyToCycle { field: "a".to_owned() });
+
+        // A field that waits on a cycle is still reported as unknown if it also copies into a missing field
+        assert_eq!(apply_mapping(r#"{"fields": {"a": {"copy_to": ["b"]}, "b": {"copy_to": ["a", "missing"]}}}"#).unwrap_err(), MappingError::UnknownField { field: "missing".to_owned() });
+
+        assert_eq!(apply_mapping(r#"{"fields": {"title": {"boost": -2.0}}}"#).unwrap_err(), MappingError::InvalidBoost { field: "title".to_owned(), boost: -2.0 });
+    }
+
+    #[test]
+    fn test_index_empty_tokens() {
+        let mut db = make_database();
+
+        // Older clients send the tokens of an empty text field as an empty list
+        let doc = serde_json::from_str(r#"{"pk": "2", "fields": {"title": []}}"#).unwrap();
+        assert!(index_document(&mut db, &doc).is_ok());
+        assert!(db.get_document_by_pk("2").is_some());
+
+        let doc = serde_json::from_str(r#"{"pk": "3", "fields": {"title": ["hello"]}}"#).unwrap();
+        assert!(index_document(&mut db, &doc).is_err());
+    }
+
+    #[test]
+    fn test_match_none() {
+        let db = make_database();
+
+        assert_eq!(query(r#""MatchNone""#).as_query(&db), Query::MatchNone);
+        assert_eq!(db.count(&query(r#""MatchNone""#).as_query(&db)), 0);
+        assert_eq!(db.count(&query(r#""MatchAll""#).as_query(&db)), 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        let db = make_database();
+
+        assert_eq!(problems(&db, r#"{"Term": {"field": "tags", "term": "red"}}"#), serde_json::json!([]));
+        assert_eq!(problems(&db, r#"{"Term": {"field": "missing", "term": "red"}}"#), serde_json::json!([
+            {"severity": "error", "path": "Term", "problem": "unknown_field", "field": "missing"},
+        ]));
+
+        // Terms are only resolved in the field they were indexed in
+        assert_eq!(problems(&db, r#"{"Term": {"field": "title", "term": "red"}}"#), serde_json::json!([
+            {"severity": "warning", "path": "Term", "problem": "unresolved_term", "field": "title", "term": "red"},
+        ]));
+        assert_eq!(problems(&db, r#"{"MultiMatch": {"query": "hello red", "fields": ["title^2", "tags"]}}"#), serde_json::json!([
+            {"severity": "warning", "path": "MultiMatch", "problem": "unresolved_term", "field": "title", "term": "red"},
+            {"severity": "warning", "path": "MultiMatch", "problem": "unresolved_term", "field": "tags", "term": "hello"},
+        ]));
+
+        // Problems in nested queries have the path to them
+        assert_eq!(problems(&db, r#"{"And": [{"Exists": {"field": "title"}}, {"Boost": {"query": {"Range": {"field": "title", "gte": 1}}, "boost": -1.0}}]}"#), serde_json::json!([
+            {"severity": "error", "path": "And.1.Boost", "problem": "invalid_boost", "boost": -1.0},
+            {"severity": "error", "path": "And.1.Boost.query.Range", "problem": "invalid_field_type", "field": "title", "field_type": "text"},
+        ]));
+        assert_eq!(problems(&db, r#"{"Range": {"field": "rating", "gte": "soon"}}"#), serde_json::json!([
+            {"severity": "error", "path": "Range", "problem": "invalid_range_bound", "field": "rating", "bound": "gte"},
+        ]));
+    }
+
+    #[test]
+    fn test_check() {
+        let db = make_database();
+
+        // Warnings don't stop the query from being run, errors do
+        assert!(query(r#"{"Term": {"field": "title", "term": "missing"}}"#).check(&db).is_ok());
+        assert!(query(r#"{"Term": {"field": "missing", "term": "hello"}}"#).check(&db).is_err());
+    }
+
+    #[test]
+    fn test_open_with_invalid_aliases() {
+        let directory = std::env::temp_dir().join(format!("sparrow-aliases-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        // A data directory that doesn't exist yet is empty
+        assert!(IndexRegistry::open(directory.clone()).is_ok());
+
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join(ALIASES_FILENAME), "{\"articles\": ").unwrap();
+        assert!(matches!(IndexRegistry::open(directory.clone()), Err(OpenError::InvalidAliases(_))));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_tokens_to_text() {
+        assert_eq!(tokens_to_text(&[token("hello", 1, None), token("world", 2, None)]), "hello world");
+        assert_eq!(tokens_to_text(&[token("world", 2, Some((8, 13))), token("hello", 1, Some((0, 5)))]), "hello   world");
+        assert_eq!(tokens_to_text(&[]), "");
+    }
 }
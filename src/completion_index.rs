@@ -0,0 +1,300 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
+use fnv::FnvHashMap;
+
+use super::DocumentId;
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct CompletionEntry {
+    pub input: String,
+    pub weight: f32,
+    pub document_id: DocumentId,
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+struct TrieNode {
+    parent: Option<usize>,
+    children: BTreeMap<char, usize>,
+    entries: Vec<usize>,
+
+    // The highest weight of any entry at or below this node. Weights can be negative, so this starts at -infinity
+    max_weight: f32,
+}
+
+impl Default for TrieNode {
+    fn default() -> TrieNode {
+        TrieNode {
+            parent: None,
+            children: BTreeMap::new(),
+            entries: Vec::new(),
+            max_weight: f32::NEG_INFINITY,
+        }
+    }
+}
+
+// A trie over whole input strings, used for type-ahead. Each node knows the highest weight below it,
+// so the best completions can be found without visiting every entry that starts with the prefix
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct CompletionIndex {
+    nodes: Vec<TrieNode>,
+    entries: Vec<Option<CompletionEntry>>,
+    document_entries: FnvHashMap<DocumentId, Vec<(usize, usize)>>,
+}
+
+impl Default for CompletionIndex {
+    fn default() -> CompletionIndex {
+        CompletionIndex {
+            nodes: vec![TrieNode::default()],
+            entries: Vec::new(),
+            document_entries: FnvHashMap::default(),
+        }
+    }
+}
+
+// Inputs are matched case insensitively
+fn normalize(input: &str) -> String {
+    input.trim().to_lowercase()
+}
+
+// An item in the queue of the best-first search. Nodes are ranked by the highest weight below them
+#[derive(Debug, PartialEq)]
+enum Candidate {
+    Node(usize, f32),
+    Entry(usize, f32),
+}
+
+impl Candidate {
+    fn weight(&self) -> f32 {
+        match self {
+            Candidate::Node(_, weight) | Candidate::Entry(_, weight) => *weight,
+        }
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        // Entries come out before nodes with the same weight, so that they're returned as soon as they can be
+        self.weight().partial_cmp(&other.weight()).unwrap_or(Ordering::Equal).then_with(|| match (self, other) {
+            (Candidate::Entry(a, _), Candidate::Entry(b, _)) => b.cmp(a),
+            (Candidate::Entry(..), Candidate::Node(..)) => Ordering::Greater,
+            (Candidate::Node(..), Candidate::Entry(..)) => Ordering::Less,
+            (Candidate::Node(a, _), Candidate::Node(b, _)) => b.cmp(a),
+        })
+    }
+}
+
+impl CompletionIndex {
+    pub fn insert(&mut self, document_id: DocumentId, input: &str, weight: f32) {
+        let key = normalize(input);
+        if key.is_empty() {
+            return;
+        }
+
+        let mut node = 0;
+        self.nodes[node].max_weight = self.nodes[node].max_weight.max(weight);
+
+        for c in key.chars() {
+            node = match self.nodes[node].children.get(&c) {
+                Some(child) => *child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(TrieNode { parent: Some(node), ..TrieNode::default() });
+                    self.nodes[node].children.insert(c, child);
+                    child
+                }
+            };
+
+            self.nodes[node].max_weight = self.nodes[node].max_weight.max(weight);
+        }
+
+        let entry = self.entries.len();
+        self.entries.push(Some(CompletionEntry { input: input.to_owned(), weight, document_id }));
+        self.nodes[node].entries.push(entry);
+        self.document_entries.entry(document_id).or_default().push((node, entry));
+    }
+
+    pub fn delete(&mut self, document_id: DocumentId) {
+        for (node, entry) in self.document_entries.remove(&document_id).unwrap_or_default() {
+            self.nodes[node].entries.retain(|other_entry| *other_entry != entry);
+            self.entries[entry] = None;
+            self.update_max_weights(node);
+        }
+    }
+
+    // Recalculates the highest weight of the node and its ancestors after an entry below it has been removed
+    fn update_max_weights(&mut self, node: usize) {
+        let mut node = Some(node);
+
+        while let Some(current) = node {
+            let entries_weight = self.nodes[current].entries.iter().filter_map(|entry| self.entries[*entry].as_ref()).map(|entry| entry.weight).fold(f32::NEG_INFINITY, f32::max);
+            let children_weight = self.nodes[current].children.values().map(|child| self.nodes[*child].max_weight).fold(f32::NEG_INFINITY, f32::max);
+            let max_weight = entries_weight.max(children_weight);
+
+            // The ancestors can't change if this node hasn't
+            if max_weight == self.nodes[current].max_weight {
+                break;
+            }
+
+            self.nodes[current].max_weight = max_weight;
+            node = self.nodes[current].parent;
+        }
+    }
+
+    // Finds the node for each prefix of the trie that is within the given number of edits of the prefix
+    // Nodes below a node that matched aren't returned, as the completions below them are found from that node
+    fn find_prefix_nodes(&self, prefix: &str, fuzziness: usize) -> Vec<usize> {
+        let prefix = prefix.chars().collect::<Vec<_>>();
+        let mut nodes = Vec::new();
+
+        // Each item is a node and the edit distances between the path to it and each prefix of the input
+        let mut stack = vec![(0, (0..=prefix.len()).collect::<Vec<_>>())];
+
+        while let Some((node, row)) = stack.pop() {
+            if row[prefix.len()] <= fuzziness {
+                nodes.push(node);
+                continue;
+            }
+
+            // None of the paths below this node can get any closer than the closest cell of this row
+            if row.iter().min().cloned().unwrap_or(0) > fuzziness {
+                continue;
+            }
+
+            for (c, child) in &self.nodes[node].children {
+                let mut next_row = vec![row[0] + 1; prefix.len() + 1];
+                for i in 1..=prefix.len() {
+                    let substitution_cost = if prefix[i - 1] == *c { 0 } else { 1 };
+                    next_row[i] = (row[i - 1] + substitution_cost).min(row[i] + 1).min(next_row[i - 1] + 1);
+                }
+
+                stack.push((*child, next_row));
+            }
+        }
+
+        nodes
+    }
+
+    // Finds the highest weighted entries that start with the prefix, allowing for a number of typos in the prefix
+    pub fn complete(&self, prefix: &str, size: usize, fuzziness: usize) -> Vec<&CompletionEntry> {
+        let mut queue = self.find_prefix_nodes(&normalize(prefix), fuzziness).into_iter().map(|node| Candidate::Node(node, self.nodes[node].max_weight)).collect::<BinaryHeap<_>>();
+        let mut results = Vec::new();
+
+        while results.len() < size {
+            match queue.pop() {
+                Some(Candidate::Entry(entry, _)) => {
+                    if let Some(entry) = &self.entries[entry] {
+                        results.push(entry);
+                    }
+                }
+                Some(Candidate::Node(node, _)) => {
+                    let node = &self.nodes[node];
+
+                    for entry in &node.entries {
+                        if let Some(completion_entry) = &self.entries[*entry] {
+                            queue.push(Candidate::Entry(*entry, completion_entry.weight));
+                        }
+                    }
+
+                    for child in node.children.values() {
+                        queue.push(Candidate::Node(*child, self.nodes[*child].max_weight));
+                    }
+                }
+                None => break,
+            }
+        }
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DocumentId;
+    use super::CompletionIndex;
+
+    fn complete(index: &CompletionIndex, prefix: &str, size: usize, fuzziness: usize) -> Vec<String> {
+        index.complete(prefix, size, fuzziness).into_iter().map(|entry| entry.input.clone()).collect()
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut index = CompletionIndex::default();
+        index.insert(DocumentId(0), "Nevermind", 5.0);
+        index.insert(DocumentId(1), "Nirvana", 10.0);
+        index.insert(DocumentId(2), "Nine Inch Nails", 7.0);
+        index.insert(DocumentId(3), "Oasis", 20.0);
+
+        assert_eq!(complete(&index, "n", 10, 0), vec!["Nirvana", "Nine Inch Nails", "Nevermind"]);
+        assert_eq!(complete(&index, "N", 2, 0), vec!["Nirvana", "Nine Inch Nails"]);
+        assert_eq!(complete(&index, "nin", 10, 0), vec!["Nine Inch Nails"]);
+        assert_eq!(complete(&index, "nx", 10, 0), Vec::<String>::new());
+        assert_eq!(complete(&index, "", 2, 0), vec!["Oasis", "Nirvana"]);
+    }
+
+    #[test]
+    fn test_complete_fuzzy() {
+        let mut index = CompletionIndex::default();
+        index.insert(DocumentId(0), "Nirvana", 10.0);
+        index.insert(DocumentId(1), "Nine Inch Nails", 7.0);
+
+        assert_eq!(complete(&index, "nrv", 10, 0), Vec::<String>::new());
+        assert_eq!(complete(&index, "nrv", 10, 1), vec!["Nirvana"]);
+        assert_eq!(complete(&index, "mine", 10, 1), vec!["Nine Inch Nails"]);
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut index = CompletionIndex::default();
+        index.insert(DocumentId(0), "Nirvana", 10.0);
+        index.insert(DocumentId(0), "Kurt Cobain", 10.0);
+        index.insert(DocumentId(1), "Nine Inch Nails", 7.0);
+        index.delete(DocumentId(0));
+
+        assert_eq!(complete(&index, "n", 10, 0), vec!["Nine Inch Nails"]);
+        assert_eq!(complete(&index, "k", 10, 0), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_delete_lowers_max_weight() {
+        let mut index = CompletionIndex::default();
+        index.insert(DocumentId(0), "Nirvana", 10.0);
+        index.insert(DocumentId(1), "Nine Inch Nails", 7.0);
+        index.insert(DocumentId(2), "Nickelback", 2.0);
+        index.delete(DocumentId(0));
+
+        assert_eq!(index.nodes[0].max_weight, 7.0);
+        let n = index.nodes[0].children[&'n'];
+        let ni = index.nodes[n].children[&'i'];
+        assert_eq!(index.nodes[n].max_weight, 7.0);
+        assert_eq!(index.nodes[ni].max_weight, 7.0);
+        assert_eq!(index.nodes[index.nodes[ni].children[&'r']].max_weight, f32::NEG_INFINITY);
+
+        index.delete(DocumentId(1));
+        assert_eq!(index.nodes[0].max_weight, 2.0);
+        assert_eq!(complete(&index, "ni", 10, 0), vec!["Nickelback"]);
+    }
+
+    #[test]
+    fn test_complete_negative_weights() {
+        let mut index = CompletionIndex::default();
+        index.insert(DocumentId(0), "Nirvana", -5.0);
+        index.insert(DocumentId(1), "Nine Inch Nails", -1.0);
+        index.insert(DocumentId(2), "Oasis", -3.0);
+        index.insert(DocumentId(3), "Nickelback", -10.0);
+
+        assert_eq!(complete(&index, "", 10, 0), vec!["Nine Inch Nails", "Oasis", "Nirvana", "Nickelback"]);
+        assert_eq!(complete(&index, "", 2, 0), vec!["Nine Inch Nails", "Oasis"]);
+
+        index.delete(DocumentId(1));
+        assert_eq!(index.nodes[0].max_weight, -3.0);
+        assert_eq!(complete(&index, "", 2, 0), vec!["Oasis", "Nirvana"]);
+    }
+}
@@ -1,18 +1,76 @@
 use std::collections::hash_map::HashMap;
 use fnv::{FnvHashMap, FnvHashSet};
 
+use super::analysis::Analyzer;
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
 #[serde(transparent)]
 pub struct FieldId(pub u32);
 
-// TODO: Field types
-#[derive(Debug, Clone, serde_derive::Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    // Analysed into a TSVector and indexed in an inverted index
+    #[default]
+    Text,
+
+    // Numbers are stored in a NumericIndex, so they can be filtered by range
+    Integer,
+    Float,
+
+    // Also stored in a NumericIndex, as milliseconds since the Unix epoch
+    Date,
+
+    // Exact values that are indexed without being tokenised. For example, primary keys or tags
+    Keyword,
+
+    // Stored in a KeywordIndex as the terms "true" and "false"
+    Boolean,
+
+    // A keyword with a chain of ancestors. For example, a Wagtail content type and the models it inherits from
+    // The last value is stored in a KeywordIndex and the links between values in a HierarchyIndex
+    Hierarchy,
+
+    // Whole strings that are indexed in a CompletionIndex for type-ahead
+    Completion,
+}
+
+impl FieldType {
+    pub fn is_numeric(&self) -> bool {
+        match self {
+            FieldType::Integer | FieldType::Float => true,
+            FieldType::Text | FieldType::Date | FieldType::Keyword | FieldType::Boolean | FieldType::Hierarchy | FieldType::Completion => false,
+        }
+    }
+
+    // Whether the field has doc values that results can be sorted by
+    // Text fields don't keep their original text, so a keyword field should be used to sort by text instead
+    pub fn is_sortable(&self) -> bool {
+        match self {
+            FieldType::Integer | FieldType::Float | FieldType::Date | FieldType::Keyword | FieldType::Boolean | FieldType::Hierarchy => true,
+            FieldType::Text | FieldType::Completion => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct FieldConfig {
+    pub field_type: FieldType,
     pub boost: f32,
     pub copy_to: FnvHashSet<FieldId>,
+    pub analyzer: Analyzer,
+
+    // Whether the original value is kept so that it can be returned with search results
+    pub stored: bool,
 }
 
 impl FieldConfig {
+    pub fn field_type(&self, field_type: FieldType) -> FieldConfig {
+        let mut new = self.clone();
+        new.field_type = field_type;
+        new
+    }
+
     pub fn boost(&self, boost: f32) -> FieldConfig {
         let mut new = self.clone();
         new.boost *= boost;
@@ -24,37 +82,166 @@ impl FieldConfig {
         new.copy_to.insert(other);
         new
     }
+
+    pub fn analyzer(&self, analyzer: Analyzer) -> FieldConfig {
+        let mut new = self.clone();
+        new.analyzer = analyzer;
+        new
+    }
+
+    pub fn stored(&self, stored: bool) -> FieldConfig {
+        let mut new = self.clone();
+        new.stored = stored;
+        new
+    }
 }
 
 impl Default for FieldConfig {
     fn default() -> FieldConfig {
         FieldConfig {
+            field_type: FieldType::default(),
             boost: 1.0,
             copy_to: FnvHashSet::default(),
+            analyzer: Analyzer::default(),
+            stored: false,
         }
     }
 }
 
-#[derive(Debug, Default, Clone, serde_derive::Serialize)]
+// Reasons that a change to the fields of an index was rejected
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum MappingError {
+    UnknownField {
+        field: String,
+    },
+
+    // Changing this setting would make the documents that are already indexed inconsistent with new ones
+    IncompatibleChange {
+        field: String,
+        setting: &'static str,
+    },
+
+    // Only text fields can be copied, and only into other text fields
+    InvalidCopyTo {
+        field: String,
+        copy_to: String,
+    },
+
+    // The field copies into itself, or into a field that copies back into it
+    CopyToCycle {
+        field: String,
+    },
+
+    // Boosts must be finite and not negative
+    InvalidBoost {
+        field: String,
+        boost: f32,
+    },
+}
+
+impl std::fmt::Display for MappingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MappingError::UnknownField { field } => write!(f, "Unknown field '{}'", field),
+            MappingError::IncompatibleChange { field, setting } => write!(f, "Cannot change the {} of field '{}' after it has been created", setting, field),
+            MappingError::InvalidCopyTo { field, copy_to } => write!(f, "Cannot copy field '{}' into '{}', both fields must be text fields", field, copy_to),
+            MappingError::CopyToCycle { field } => write!(f, "Field '{}' is copied back into itself", field),
+            MappingError::InvalidBoost { field, boost } => write!(f, "Invalid boost {} on field '{}', boosts must be finite and not negative", boost, field),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(from = "DataDictionaryData")]
 pub struct DataDictionary {
     next_field_id: u32,
     pub field_names: HashMap<String, FieldId>,
     pub fields: FnvHashMap<FieldId, FieldConfig>,
+
+    // The reverse of field_names. Not saved, it's rebuilt when the dictionary is loaded
+    #[serde(skip)]
+    names_by_id: FnvHashMap<FieldId, String>,
+}
+
+// The saved fields of a DataDictionary
+#[derive(serde_derive::Deserialize)]
+struct DataDictionaryData {
+    next_field_id: u32,
+    field_names: HashMap<String, FieldId>,
+    fields: FnvHashMap<FieldId, FieldConfig>,
+}
+
+impl From<DataDictionaryData> for DataDictionary {
+    fn from(data: DataDictionaryData) -> DataDictionary {
+        let names_by_id = data.field_names.iter().map(|(name, field_id)| (*field_id, name.clone())).collect();
+
+        DataDictionary { next_field_id: data.next_field_id, field_names: data.field_names, fields: data.fields, names_by_id }
+    }
 }
 
 impl DataDictionary {
     pub fn insert(&mut self, name: String, config: FieldConfig) -> FieldId {
         let id = FieldId(self.next_field_id);
         self.next_field_id += 1;
+        self.names_by_id.insert(id, name.clone());
         self.field_names.insert(name, id);
         self.fields.insert(id, config);
         id
     }
 
+    // Adds a field, or updates an existing field if the change doesn't affect how its values are indexed
+    pub fn put(&mut self, name: String, config: FieldConfig) -> Result<FieldId, MappingError> {
+        if !config.boost.is_finite() || config.boost < 0.0 {
+            return Err(MappingError::InvalidBoost { field: name, boost: config.boost });
+        }
+
+        for copy_to in &config.copy_to {
+            let is_text_field = self.get(*copy_to).map(|copy_to_config| copy_to_config.field_type == FieldType::Text).unwrap_or(false);
+
+            if config.field_type != FieldType::Text || !is_text_field {
+                let copy_to = self.get_name(*copy_to).unwrap_or("").to_owned();
+                return Err(MappingError::InvalidCopyTo { field: name, copy_to });
+            }
+        }
+
+        let field_id = match self.field_names.get(&name) {
+            Some(field_id) => *field_id,
+            None => return Ok(self.insert(name, config)),
+        };
+        let existing_config = self.fields.get_mut(&field_id).expect("Field name with invalid field id");
+
+        let changed_setting = if existing_config.field_type != config.field_type {
+            Some("type")
+        } else if existing_config.analyzer != config.analyzer {
+            Some("analyzer")
+        } else if existing_config.boost != config.boost {
+            // The boost is applied to the weights of terms when they are indexed
+            Some("boost")
+        } else if existing_config.copy_to != config.copy_to {
+            Some("copy_to")
+        } else {
+            None
+        };
+
+        if let Some(setting) = changed_setting {
+            return Err(MappingError::IncompatibleChange { field: name, setting });
+        }
+
+        // Storing values can be turned on or off at any time, it only affects documents that are indexed afterwards
+        existing_config.stored = config.stored;
+
+        Ok(field_id)
+    }
+
     pub fn get(&self, field_id: FieldId) -> Option<&FieldConfig> {
         self.fields.get(&field_id)
     }
 
+    pub fn get_name(&self, field_id: FieldId) -> Option<&str> {
+        self.names_by_id.get(&field_id).map(|name| name.as_str())
+    }
+
     pub fn get_by_name(&self, name: &str) -> Option<(FieldId, &FieldConfig)> {
         self.field_names.get(name).map(|field_id| {
             let field_config = self.fields.get(field_id).expect("Field name with invalid field id");
@@ -63,3 +250,47 @@ impl DataDictionary {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::Analyzer;
+    use super::{DataDictionary, FieldConfig, FieldType, MappingError};
+
+    #[test]
+    fn test_put() {
+        let mut data_dict = DataDictionary::default();
+        let all_text = data_dict.put("all_text".to_owned(), FieldConfig::default()).unwrap();
+        let title = data_dict.put("title".to_owned(), FieldConfig::default().boost(2.0).copy_to(all_text)).unwrap();
+
+        // Putting the same config again changes nothing
+        assert_eq!(data_dict.put("title".to_owned(), FieldConfig::default().boost(2.0).copy_to(all_text)), Ok(title));
+
+        // Stored can be changed
+        assert_eq!(data_dict.put("title".to_owned(), FieldConfig::default().boost(2.0).copy_to(all_text).stored(true)), Ok(title));
+        assert_eq!(data_dict.get(title).map(|config| config.stored), Some(true));
+
+        assert_eq!(data_dict.put("title".to_owned(), FieldConfig::default().copy_to(all_text)), Err(MappingError::IncompatibleChange { field: "title".to_owned(), setting: "boost" }));
+        assert_eq!(data_dict.put("title".to_owned(), FieldConfig::default().boost(2.0)), Err(MappingError::IncompatibleChange { field: "title".to_owned(), setting: "copy_to" }));
+        assert_eq!(data_dict.put("all_text".to_owned(), FieldConfig::default().field_type(FieldType::Keyword)), Err(MappingError::IncompatibleChange { field: "all_text".to_owned(), setting: "type" }));
+        assert_eq!(data_dict.put("all_text".to_owned(), FieldConfig::default().analyzer(Analyzer::Keyword)), Err(MappingError::IncompatibleChange { field: "all_text".to_owned(), setting: "analyzer" }));
+
+        let tags = data_dict.put("tags".to_owned(), FieldConfig::default().field_type(FieldType::Keyword)).unwrap();
+        assert_eq!(data_dict.put("summary".to_owned(), FieldConfig::default().copy_to(tags)), Err(MappingError::InvalidCopyTo { field: "summary".to_owned(), copy_to: "tags".to_owned() }));
+        assert_eq!(data_dict.put("pk".to_owned(), FieldConfig::default().field_type(FieldType::Keyword).copy_to(all_text)), Err(MappingError::InvalidCopyTo { field: "pk".to_owned(), copy_to: "all_text".to_owned() }));
+
+        assert_eq!(data_dict.put("body".to_owned(), FieldConfig::default().boost(-1.0)), Err(MappingError::InvalidBoost { field: "body".to_owned(), boost: -1.0 }));
+        assert!(matches!(data_dict.put("body".to_owned(), FieldConfig::default().boost(f32::NAN)), Err(MappingError::InvalidBoost { .. })));
+        assert!(data_dict.get_by_name("body").is_none());
+    }
+
+    #[test]
+    fn test_get_name_after_load() {
+        let mut data_dict = DataDictionary::default();
+        let title = data_dict.put("title".to_owned(), FieldConfig::default()).unwrap();
+        assert_eq!(data_dict.get_name(title), Some("title"));
+
+        let data_dict: DataDictionary = bincode::deserialize(&bincode::serialize(&data_dict).unwrap()).unwrap();
+        assert_eq!(data_dict.get_name(title), Some("title"));
+        assert_eq!(data_dict.get_by_name("title").map(|(field_id, _)| field_id), Some(title));
+    }
+}
@@ -0,0 +1,377 @@
+// Dates are represented as milliseconds since the Unix epoch (UTC)
+
+const MILLIS_PER_SECOND: i64 = 1000;
+const MILLIS_PER_MINUTE: i64 = 60 * MILLIS_PER_SECOND;
+const MILLIS_PER_HOUR: i64 = 60 * MILLIS_PER_MINUTE;
+const MILLIS_PER_DAY: i64 = 24 * MILLIS_PER_HOUR;
+
+// Converts a date into the number of days since 1970-01-01
+// See: http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The inverse of days_from_civil
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 => if is_leap_year(year) { 29 } else { 28 },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_number(text: &str, digits: usize) -> Option<u32> {
+    if text.len() != digits || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    text.parse().ok()
+}
+
+// Parses a timezone offset such as "Z", "+01:00" or "-0500" into milliseconds
+pub fn parse_offset(text: &str) -> Option<i64> {
+    if text.is_empty() || text == "Z" {
+        return Some(0);
+    }
+
+    let (sign, text) = match (text.strip_prefix('+'), text.strip_prefix('-')) {
+        (Some(text), _) => (1, text),
+        (_, Some(text)) => (-1, text),
+        _ => return None,
+    };
+
+    let text = text.replace(':', "");
+    let hours = parse_number(text.get(..2)?, 2)? as i64;
+    let minutes = parse_number(text.get(2..)?, 2)? as i64;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+
+    Some(sign * (hours * MILLIS_PER_HOUR + minutes * MILLIS_PER_MINUTE))
+}
+
+// Parses an ISO-8601 date or datetime. For example: "2020-05-17", "2020-05-17T10:30:00Z" or "2020-05-17T10:30:00.123+01:00"
+// Datetimes without an offset are assumed to be in UTC
+pub fn parse_datetime(text: &str) -> Option<i64> {
+    let (date, time) = match text.find(['T', ' ']) {
+        Some(index) => (&text[..index], Some(&text[index + 1..])),
+        None => (text, None),
+    };
+
+    let mut date_parts = date.split('-');
+    let year = parse_number(date_parts.next()?, 4)? as i64;
+    let month = parse_number(date_parts.next()?, 2)?;
+    let day = parse_number(date_parts.next()?, 2)?;
+
+    if date_parts.next().is_some() || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut millis = days_from_civil(year, month, day) * MILLIS_PER_DAY;
+
+    if let Some(time) = time {
+        let offset_start = time.find(['Z', '+', '-']).unwrap_or(time.len());
+        let (time, offset) = time.split_at(offset_start);
+        let (time, fraction) = match time.find('.') {
+            Some(index) => (&time[..index], Some(&time[index + 1..])),
+            None => (time, None),
+        };
+
+        let mut time_parts = time.split(':');
+        let hours = parse_number(time_parts.next()?, 2)?;
+        let minutes = parse_number(time_parts.next()?, 2)?;
+        let seconds = match time_parts.next() {
+            Some(seconds) => parse_number(seconds, 2)?,
+            None => 0,
+        };
+
+        if time_parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {
+            return None;
+        }
+
+        millis += hours as i64 * MILLIS_PER_HOUR + minutes as i64 * MILLIS_PER_MINUTE + seconds as i64 * MILLIS_PER_SECOND;
+
+        if let Some(fraction) = fraction {
+            if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+
+            // Only millisecond precision is kept
+            let fraction = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+            millis += fraction.parse::<i64>().ok()?;
+        }
+
+        millis -= parse_offset(offset)?;
+    }
+
+    Some(millis)
+}
+
+// Formats a timestamp as an ISO-8601 datetime in the timezone with the given offset
+pub fn format_datetime(millis: i64, offset: i64) -> String {
+    let local = millis + offset;
+    let (year, month, day) = civil_from_days(local.div_euclid(MILLIS_PER_DAY));
+    let time = local.rem_euclid(MILLIS_PER_DAY);
+
+    let offset_text = if offset == 0 {
+        "Z".to_owned()
+    } else {
+        let sign = if offset < 0 { '-' } else { '+' };
+        format!("{}{:02}:{:02}", sign, offset.abs() / MILLIS_PER_HOUR, offset.abs() % MILLIS_PER_HOUR / MILLIS_PER_MINUTE)
+    };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}{}",
+        year, month, day,
+        time / MILLIS_PER_HOUR, time % MILLIS_PER_HOUR / MILLIS_PER_MINUTE, time % MILLIS_PER_MINUTE / MILLIS_PER_SECOND, time % MILLIS_PER_SECOND,
+        offset_text
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DateUnit {
+    Year,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl DateUnit {
+    fn from_char(c: char) -> Option<DateUnit> {
+        match c {
+            'y' => Some(DateUnit::Year),
+            'M' => Some(DateUnit::Month),
+            'w' => Some(DateUnit::Week),
+            'd' => Some(DateUnit::Day),
+            'h' | 'H' => Some(DateUnit::Hour),
+            'm' => Some(DateUnit::Minute),
+            's' => Some(DateUnit::Second),
+            _ => None,
+        }
+    }
+}
+
+// Years further from 1970 than this can't be represented in milliseconds
+const MAX_YEARS: i64 = i64::MAX / MILLIS_PER_DAY / 366;
+
+fn add_months(millis: i64, months: i64) -> Option<i64> {
+    let days = millis.div_euclid(MILLIS_PER_DAY);
+    let time = millis.rem_euclid(MILLIS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    let month_index = (year * 12 + (month as i64 - 1)).checked_add(months)?;
+    let year = month_index.div_euclid(12);
+    let month = (month_index.rem_euclid(12) + 1) as u32;
+    if (year - 1970).abs() > MAX_YEARS {
+        return None;
+    }
+
+    // Clamp to the end of the month, so 31st January + 1 month is 29th February in a leap year
+    let day = day.min(days_in_month(year, month));
+    days_from_civil(year, month, day).checked_mul(MILLIS_PER_DAY)?.checked_add(time)
+}
+
+// Returns None if the result is too far from 1970 to be represented
+pub fn add(millis: i64, amount: i64, unit: DateUnit) -> Option<i64> {
+    let add_millis = |unit_millis: i64| amount.checked_mul(unit_millis).and_then(|amount_millis| millis.checked_add(amount_millis));
+
+    match unit {
+        DateUnit::Year => add_months(millis, amount.checked_mul(12)?),
+        DateUnit::Month => add_months(millis, amount),
+        DateUnit::Week => add_millis(7 * MILLIS_PER_DAY),
+        DateUnit::Day => add_millis(MILLIS_PER_DAY),
+        DateUnit::Hour => add_millis(MILLIS_PER_HOUR),
+        DateUnit::Minute => add_millis(MILLIS_PER_MINUTE),
+        DateUnit::Second => add_millis(MILLIS_PER_SECOND),
+    }
+}
+
+// Rounds down to the start of the unit that contains the given time
+// Returns None if the start of the unit is too far from 1970 to be represented
+pub fn round_down(millis: i64, unit: DateUnit) -> Option<i64> {
+    let truncate = |size: i64| millis.checked_sub(millis.rem_euclid(size));
+
+    match unit {
+        DateUnit::Year | DateUnit::Month => {
+            let (year, month, _) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+            let month = if unit == DateUnit::Year { 1 } else { month };
+            days_from_civil(year, month, 1).checked_mul(MILLIS_PER_DAY)
+        }
+        DateUnit::Week => {
+            // 1970-01-01 was a Thursday, weeks start on Monday
+            let days = millis.div_euclid(MILLIS_PER_DAY);
+            (days - (days + 3).rem_euclid(7)).checked_mul(MILLIS_PER_DAY)
+        }
+        DateUnit::Day => truncate(MILLIS_PER_DAY),
+        DateUnit::Hour => truncate(MILLIS_PER_HOUR),
+        DateUnit::Minute => truncate(MILLIS_PER_MINUTE),
+        DateUnit::Second => truncate(MILLIS_PER_SECOND),
+    }
+}
+
+// Evaluates a date math expression such as "now-7d/d" or "2020-01-01||+1M"
+//
+// The expression starts with an anchor ("now" or a date followed by "||") which is followed by any number
+// of operations. "+1d"/"-1d" add or subtract time, "/d" rounds to the start of the day.
+//
+// When round_up is set, rounding goes to the last millisecond of the unit instead. This should be used for
+// "lte" and "gt" bounds so that "lte: now/d" includes the whole of today.
+pub fn evaluate_date_math(expression: &str, now: i64, round_up: bool) -> Option<i64> {
+    let (mut millis, operations) = if let Some(operations) = expression.strip_prefix("now") {
+        (now, operations)
+    } else if let Some(index) = expression.find("||") {
+        (parse_datetime(&expression[..index])?, &expression[index + 2..])
+    } else {
+        return parse_datetime(expression);
+    };
+
+    let mut chars = operations.chars().peekable();
+
+    while let Some(operator) = chars.next() {
+        let mut amount = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            amount.push(*c);
+            chars.next();
+        }
+
+        let unit = DateUnit::from_char(chars.next()?)?;
+
+        match operator {
+            '+' | '-' => {
+                let amount = if amount.is_empty() { 1 } else { amount.parse::<i64>().ok()? };
+                millis = add(millis, if operator == '-' { -amount } else { amount }, unit)?;
+            }
+            '/' if amount.is_empty() => {
+                millis = round_down(millis, unit)?;
+
+                if round_up {
+                    millis = add(millis, 1, unit)? - 1;
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DateUnit, parse_datetime, parse_offset, round_down, format_datetime, evaluate_date_math, days_from_civil, civil_from_days};
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset(""), Some(0));
+        assert_eq!(parse_offset("Z"), Some(0));
+        assert_eq!(parse_offset("+01:00"), Some(3_600_000));
+        assert_eq!(parse_offset("-0530"), Some(-19_800_000));
+        assert_eq!(parse_offset("+23:59"), Some(86_340_000));
+
+        // Out of range
+        assert_eq!(parse_offset("+24:00"), None);
+        assert_eq!(parse_offset("+99:99"), None);
+        assert_eq!(parse_offset("-01:60"), None);
+
+        // Multibyte characters must not be split
+        assert_eq!(parse_offset("é01:00"), None);
+        assert_eq!(parse_offset("+é1:00"), None);
+        assert_eq!(parse_offset("+01:é"), None);
+    }
+
+    #[test]
+    fn test_round_down() {
+        let millis = parse_datetime("2020-05-17T10:30:15.500Z").unwrap();
+        assert_eq!(round_down(millis, DateUnit::Year), parse_datetime("2020-01-01"));
+        assert_eq!(round_down(millis, DateUnit::Month), parse_datetime("2020-05-01"));
+        assert_eq!(round_down(millis, DateUnit::Week), parse_datetime("2020-05-11"));
+        assert_eq!(round_down(millis, DateUnit::Second), parse_datetime("2020-05-17T10:30:15Z"));
+
+        // The start of the unit doesn't fit in an i64
+        assert_eq!(round_down(i64::MIN, DateUnit::Year), None);
+        assert_eq!(round_down(i64::MIN, DateUnit::Week), None);
+        assert_eq!(round_down(i64::MIN, DateUnit::Second), None);
+        assert!(round_down(i64::MAX, DateUnit::Month).is_some());
+    }
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        assert_eq!(parse_datetime("1970-01-01"), Some(0));
+        assert_eq!(parse_datetime("2020-05-17"), Some(1589673600000));
+        assert_eq!(parse_datetime("2020-05-17T10:30:00Z"), Some(1589711400000));
+        assert_eq!(parse_datetime("2020-05-17T10:30:00.5Z"), Some(1589711400500));
+        assert_eq!(parse_datetime("2020-05-17T11:30:00+01:00"), Some(1589711400000));
+        assert_eq!(parse_datetime("2020-05-17T10:30"), Some(1589711400000));
+
+        assert_eq!(parse_datetime("2020-02-30"), None);
+        assert_eq!(parse_datetime("2020-5-17"), None);
+        assert_eq!(parse_datetime("2020-05-17T25:00:00"), None);
+        assert_eq!(parse_datetime("yesterday"), None);
+    }
+
+    #[test]
+    fn test_format_datetime() {
+        assert_eq!(format_datetime(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_datetime(1589711400500, 0), "2020-05-17T10:30:00.500Z");
+        assert_eq!(format_datetime(1589711400000, 60 * 60 * 1000), "2020-05-17T11:30:00.000+01:00");
+        assert_eq!(format_datetime(1589711400000, -330 * 60 * 1000), "2020-05-17T05:00:00.000-05:30");
+    }
+
+    #[test]
+    fn test_date_math() {
+        let now = parse_datetime("2020-05-17T10:30:00Z").unwrap();
+
+        assert_eq!(evaluate_date_math("now", now, false), Some(now));
+        assert_eq!(evaluate_date_math("now-7d", now, false), parse_datetime("2020-05-10T10:30:00Z"));
+        assert_eq!(evaluate_date_math("now-7d/d", now, false), parse_datetime("2020-05-10"));
+        assert_eq!(evaluate_date_math("now/d", now, true), parse_datetime("2020-05-17T23:59:59.999Z"));
+        assert_eq!(evaluate_date_math("now/M", now, false), parse_datetime("2020-05-01"));
+        assert_eq!(evaluate_date_math("now/w", now, false), parse_datetime("2020-05-11"));
+        assert_eq!(evaluate_date_math("2020-01-31||+1M", now, false), parse_datetime("2020-02-29"));
+        assert_eq!(evaluate_date_math("2020-01-01", now, false), parse_datetime("2020-01-01"));
+
+        assert_eq!(evaluate_date_math("now-7x", now, false), None);
+        assert_eq!(evaluate_date_math("now/7d", now, false), None);
+
+        // Amounts that would overflow are rejected
+        assert_eq!(evaluate_date_math("now+999999999999999d", now, false), None);
+        assert_eq!(evaluate_date_math("now-999999999999999999y", now, false), None);
+        assert_eq!(evaluate_date_math("now+9999999999999999999M", now, false), None);
+        assert_eq!(evaluate_date_math("now+999999999999M", now, false), None);
+    }
+}
@@ -0,0 +1,28 @@
+use super::DocumentId;
+
+// Errors returned by the database. These are serialized with the kind of error in the "error" field
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum Error {
+    UnknownField {
+        field: String,
+    },
+    DocumentNotFound {
+        document_id: DocumentId,
+    },
+    InvalidQuery {
+        message: String,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::UnknownField { field } => write!(f, "Unknown field '{}'", field),
+            Error::DocumentNotFound { document_id } => write!(f, "Document {} not found", document_id.0),
+            Error::InvalidQuery { message } => write!(f, "Invalid query: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
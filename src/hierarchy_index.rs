@@ -0,0 +1,64 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use super::term_dictionary::TermId;
+
+// Records the parent/child links between the values of a hierarchical field, such as a model and its subclasses
+// The values themselves are indexed in a KeywordIndex, this is only used to find the descendants of a value
+#[derive(Debug, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct HierarchyIndex {
+    pub children: FnvHashMap<TermId, FnvHashSet<TermId>>,
+}
+
+impl HierarchyIndex {
+    // Inserts a chain of values, starting with the root
+    pub fn insert(&mut self, chain: &[TermId]) {
+        for pair in chain.windows(2) {
+            self.children.entry(pair[0]).or_default().insert(pair[1]);
+        }
+    }
+
+    // Finds the value and all of the values below it in the hierarchy
+    pub fn descendants(&self, term: TermId) -> Vec<TermId> {
+        let mut seen = FnvHashSet::default();
+        let mut stack = vec![term];
+        let mut descendants = Vec::new();
+
+        while let Some(term) = stack.pop() {
+            if !seen.insert(term) {
+                continue;
+            }
+
+            descendants.push(term);
+
+            if let Some(children) = self.children.get(&term) {
+                stack.extend(children.iter().cloned());
+            }
+        }
+
+        descendants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::term_dictionary::TermId;
+    use super::HierarchyIndex;
+
+    #[test]
+    fn test_descendants() {
+        let mut hierarchy = HierarchyIndex::default();
+        hierarchy.insert(&[TermId(1), TermId(2), TermId(3)]);
+        hierarchy.insert(&[TermId(1), TermId(4)]);
+        hierarchy.insert(&[TermId(5)]);
+
+        let mut descendants = hierarchy.descendants(TermId(1));
+        descendants.sort_by_key(|term| term.0);
+        assert_eq!(descendants, vec![TermId(1), TermId(2), TermId(3), TermId(4)]);
+
+        assert_eq!(hierarchy.descendants(TermId(2)), vec![TermId(2), TermId(3)]);
+        assert_eq!(hierarchy.descendants(TermId(5)), vec![TermId(5)]);
+
+        // Values that have never been inserted are their own only descendant
+        assert_eq!(hierarchy.descendants(TermId(6)), vec![TermId(6)]);
+    }
+}
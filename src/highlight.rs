@@ -0,0 +1,234 @@
+use fnv::FnvHashMap;
+
+use super::tsvector::TSVector;
+use super::term_dictionary::TermId;
+use super::data_dictionary::FieldId;
+use super::query::Query;
+
+#[derive(Debug, Clone)]
+pub struct HighlightOptions {
+    pub pre_tag: String,
+    pub post_tag: String,
+
+    // The maximum number of fragments to return. If zero, the whole text is returned as a single fragment
+    pub number_of_fragments: usize,
+
+    // The maximum length of each fragment in bytes. Fragments are made longer if they would otherwise split a highlight
+    pub fragment_size: usize,
+}
+
+impl Default for HighlightOptions {
+    fn default() -> HighlightOptions {
+        HighlightOptions {
+            pre_tag: "<em>".to_owned(),
+            post_tag: "</em>".to_owned(),
+            number_of_fragments: 3,
+            fragment_size: 100,
+        }
+    }
+}
+
+// Finds the positions in a field that were matched by the query, as inclusive ranges
+// A phrase match is returned as a single range so it gets highlighted as a unit
+pub fn matched_positions(query: &Query, field: FieldId, tsvector: &TSVector) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    collect_matched_positions(query, field, tsvector, &mut ranges);
+    ranges.sort();
+    ranges.dedup();
+    ranges
+}
+
+fn collect_term_positions(term: &TermId, tsvector: &TSVector, ranges: &mut Vec<(usize, usize)>) {
+    if let Some(term_info) = tsvector.terms.get(term) {
+        ranges.extend(term_info.positions.iter().map(|position| (*position, *position)));
+    }
+}
+
+fn collect_matched_positions(query: &Query, field: FieldId, tsvector: &TSVector, ranges: &mut Vec<(usize, usize)>) {
+    match query {
+        Query::Term(term_field, term) if *term_field == field => collect_term_positions(term, tsvector, ranges),
+        Query::TermSet(term_field, terms) if *term_field == field => {
+            for term in terms {
+                collect_term_positions(term, tsvector, ranges);
+            }
+        }
+        Query::BlendedTerm(fields, term) if fields.iter().any(|(term_field, _)| *term_field == field) => collect_term_positions(term, tsvector, ranges),
+        Query::Phrase(phrase_field, terms) if *phrase_field == field => {
+            if let Some(first_term) = terms.first().and_then(|term| tsvector.terms.get(term)) {
+                for position in &first_term.positions {
+                    let is_match = terms.iter().enumerate().skip(1).all(|(i, term)| {
+                        tsvector.terms.get(term).map(|term_info| term_info.positions.contains(&(position + i))).unwrap_or(false)
+                    });
+
+                    if is_match {
+                        ranges.push((*position, position + terms.len() - 1));
+                    }
+                }
+            }
+        }
+        Query::Or(queries) | Query::And(queries) | Query::DisMax(queries) => {
+            for query in queries {
+                collect_matched_positions(query, field, tsvector, ranges);
+            }
+        }
+        // Filters only decide which documents match, so they aren't highlighted
+        Query::Filter(query, _) | Query::Exclude(query, _) | Query::Boost(query, _) | Query::ConstantScore(query, _) => {
+            collect_matched_positions(query, field, tsvector, ranges);
+        }
+        _ => {}
+    }
+}
+
+// Wraps the highlights that are inside the fragment in tags
+fn render_fragment(text: &str, fragment: (usize, usize), highlights: &[(usize, usize)], options: &HighlightOptions) -> String {
+    let mut output = String::new();
+    let mut cursor = fragment.0;
+
+    for (start, end) in highlights {
+        if *end <= fragment.0 || *start >= fragment.1 {
+            continue;
+        }
+
+        let (start, end) = (*start.max(&fragment.0), *end.min(&fragment.1));
+        output.push_str(&text[cursor..start]);
+        output.push_str(&options.pre_tag);
+        output.push_str(&text[start..end]);
+        output.push_str(&options.post_tag);
+        cursor = end;
+    }
+
+    output.push_str(&text[cursor..fragment.1]);
+    output
+}
+
+// Builds the fragments of the text that contain the most highlights
+// The offsets map each token position in the text to its byte range
+pub fn highlight(text: &str, offsets: &FnvHashMap<usize, (usize, usize)>, matched_positions: &[(usize, usize)], options: &HighlightOptions) -> Vec<String> {
+    let is_valid_range = |(start, end): &(usize, usize)| start < end && *end <= text.len() && text.is_char_boundary(*start) && text.is_char_boundary(*end);
+
+    let mut highlights = matched_positions.iter().filter_map(|(start, end)| {
+        Some((offsets.get(start)?.0, offsets.get(end)?.1))
+    }).filter(is_valid_range).collect::<Vec<_>>();
+    highlights.sort();
+
+    // Merge highlights that overlap
+    let mut merged_highlights: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in highlights {
+        match merged_highlights.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged_highlights.push((start, end)),
+        }
+    }
+
+    if merged_highlights.is_empty() {
+        return Vec::new();
+    }
+
+    if options.number_of_fragments == 0 {
+        return vec![render_fragment(text, (0, text.len()), &merged_highlights, options)];
+    }
+
+    // Split the text into fragments on token boundaries
+    let mut token_offsets = offsets.values().cloned().filter(is_valid_range).collect::<Vec<_>>();
+    token_offsets.sort();
+
+    let mut fragments: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in token_offsets {
+        let is_inside_highlight = merged_highlights.iter().any(|(highlight_start, highlight_end)| *highlight_start < start && start < *highlight_end);
+
+        match fragments.last_mut() {
+            Some(fragment) if is_inside_highlight || end - fragment.0 <= options.fragment_size => fragment.1 = fragment.1.max(end),
+            _ => fragments.push((start, end)),
+        }
+    }
+
+    // Pick the fragments with the most highlights, then put them back into the order they appear in the text
+    let mut scored_fragments = fragments.into_iter().map(|fragment| {
+        let score = merged_highlights.iter().filter(|(start, _)| fragment.0 <= *start && *start < fragment.1).count();
+        (fragment, score)
+    }).filter(|(_, score)| *score > 0).collect::<Vec<_>>();
+    scored_fragments.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    scored_fragments.truncate(options.number_of_fragments);
+    scored_fragments.sort_by_key(|(fragment, _)| *fragment);
+
+    scored_fragments.into_iter().map(|(fragment, _)| render_fragment(text, fragment, &merged_highlights, options)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use fnv::FnvHashMap;
+
+    use crate::analysis::Analyzer;
+    use crate::tsvector::TSVector;
+    use crate::term_dictionary::TermDictionary;
+    use crate::data_dictionary::FieldId;
+    use crate::query::Query;
+    use super::{matched_positions, highlight, HighlightOptions};
+
+    fn analyze(text: &str, term_dict: &mut TermDictionary) -> (TSVector, FnvHashMap<usize, (usize, usize)>) {
+        let mut tsvector = TSVector::default();
+        let mut offsets = FnvHashMap::default();
+
+        for token in Analyzer::Standard.analyze(text) {
+            let term = term_dict.get_or_insert(&token.term);
+            tsvector.terms.entry(term).or_default().positions.push(token.position);
+            tsvector.length += 1;
+            offsets.insert(token.position, token.offsets.unwrap());
+        }
+
+        (tsvector, offsets)
+    }
+
+    #[test]
+    fn test_highlight_terms() {
+        let mut term_dict = TermDictionary::default();
+        let text = "The quick brown fox jumps over the lazy dog.";
+        let (tsvector, offsets) = analyze(text, &mut term_dict);
+
+        let query = Query::or(vec![
+            Query::term(FieldId(0), term_dict.get_or_insert("fox")),
+            Query::term(FieldId(0), term_dict.get_or_insert("dog")),
+            Query::term(FieldId(1), term_dict.get_or_insert("lazy")),
+        ]);
+        let positions = matched_positions(&query, FieldId(0), &tsvector);
+        assert_eq!(positions, vec![(4, 4), (9, 9)]);
+
+        let options = HighlightOptions { number_of_fragments: 0, ..HighlightOptions::default() };
+        assert_eq!(highlight(text, &offsets, &positions, &options), vec!["The quick brown <em>fox</em> jumps over the lazy <em>dog</em>."]);
+    }
+
+    #[test]
+    fn test_highlight_phrase() {
+        let mut term_dict = TermDictionary::default();
+        let text = "brown fox, and a brown dog";
+        let (tsvector, offsets) = analyze(text, &mut term_dict);
+
+        let query = Query::phrase(FieldId(0), vec![term_dict.get_or_insert("brown"), term_dict.get_or_insert("fox")]);
+        let positions = matched_positions(&query, FieldId(0), &tsvector);
+        assert_eq!(positions, vec![(1, 2)]);
+
+        let options = HighlightOptions { pre_tag: "[".to_owned(), post_tag: "]".to_owned(), number_of_fragments: 0, ..HighlightOptions::default() };
+        assert_eq!(highlight(text, &offsets, &positions, &options), vec!["[brown fox], and a brown dog"]);
+    }
+
+    #[test]
+    fn test_highlight_fragments() {
+        let mut term_dict = TermDictionary::default();
+        let text = "one two three four five six seven eight nine ten";
+        let (tsvector, offsets) = analyze(text, &mut term_dict);
+
+        let query = Query::or(vec![
+            Query::term(FieldId(0), term_dict.get_or_insert("two")),
+            Query::term(FieldId(0), term_dict.get_or_insert("nine")),
+            Query::term(FieldId(0), term_dict.get_or_insert("ten")),
+        ]);
+        let positions = matched_positions(&query, FieldId(0), &tsvector);
+
+        let options = HighlightOptions { number_of_fragments: 2, fragment_size: 15, ..HighlightOptions::default() };
+        assert_eq!(highlight(text, &offsets, &positions, &options), vec!["one <em>two</em> three", "<em>nine</em> <em>ten</em>"]);
+
+        // The fragment with the most highlights is picked first
+        let options = HighlightOptions { number_of_fragments: 1, fragment_size: 15, ..HighlightOptions::default() };
+        assert_eq!(highlight(text, &offsets, &positions, &options), vec!["<em>nine</em> <em>ten</em>"]);
+    }
+}
@@ -0,0 +1,48 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use super::DocumentId;
+use super::term_dictionary::TermId;
+
+// Indexes exact values. Unlike InvertedIndex, this doesn't store positions or field lengths
+#[derive(Debug, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct KeywordIndex {
+    pub postings: FnvHashMap<TermId, Vec<DocumentId>>,
+
+    // The values of each document, indexed by document id
+    pub doc_values: Vec<Vec<TermId>>,
+}
+
+impl KeywordIndex {
+    pub fn insert(&mut self, document_id: DocumentId, terms: &[TermId]) {
+        let mut seen_terms = FnvHashSet::default();
+        let terms = terms.iter().cloned().filter(|term| seen_terms.insert(*term)).collect::<Vec<_>>();
+
+        for term in &terms {
+            self.postings.entry(*term).or_default().push(document_id);
+        }
+
+        let index = document_id.0 as usize;
+        if self.doc_values.len() <= index {
+            self.doc_values.resize(index + 1, Vec::new());
+        }
+        self.doc_values[index] = terms;
+    }
+
+    pub fn doc_values(&self, document_id: DocumentId) -> &[TermId] {
+        self.doc_values.get(document_id.0 as usize).map(|terms| terms.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn term_document_frequency(&self, term: TermId) -> usize {
+        self.postings.get(&term).map(|postings_list| postings_list.len()).unwrap_or(0)
+    }
+
+    pub fn docs_with_term(&self, term: TermId) -> Vec<DocumentId> {
+        self.postings.get(&term).cloned().unwrap_or_default()
+    }
+
+    pub fn search(&self, term: TermId) -> Vec<(DocumentId, f32)> {
+        // There's no field length to normalise by, so these are only scored by IDF
+        let inverse_document_frequency = 1.0 / (self.term_document_frequency(term) as f32 + 1.0).log2();
+        self.postings.get(&term).map(|postings_list| postings_list.iter().map(|document_id| (*document_id, inverse_document_frequency)).collect()).unwrap_or_default()
+    }
+}
@@ -1,7 +1,20 @@
+pub mod analysis;
+pub mod error;
 pub mod tsvector;
 pub mod term_dictionary;
 pub mod data_dictionary;
+pub mod numeric_index;
+pub mod keyword_index;
+pub mod hierarchy_index;
+pub mod completion_index;
+pub mod date;
 pub mod query;
+pub mod query_string;
+pub mod sort;
+pub mod aggregation;
+pub mod persistence;
+pub mod highlight;
+pub mod suggest;
 
 use std::collections::HashMap;
 use std::iter::FromIterator;
@@ -10,18 +23,44 @@ use fnv::{FnvHashMap, FnvHashSet};
 use tsvector::TSVector;
 use term_dictionary::{TermId, TermDictionary};
 use data_dictionary::{FieldId, DataDictionary};
+use numeric_index::{NumericIndex, NumericValue};
+use keyword_index::KeywordIndex;
+use hierarchy_index::HierarchyIndex;
+use completion_index::{CompletionIndex, CompletionEntry};
 use query::Query;
+pub use error::Error;
+use sort::{Sort, SortKey, SortOrder, SortValue};
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, serde_derive::Serialize)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, serde_derive::Serialize, serde_derive::Deserialize)]
 #[serde(transparent)]
 pub struct DocumentId(pub u32);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Default, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct Document {
     pub fields: FnvHashMap<FieldId, TSVector>,
+    pub numeric_fields: FnvHashMap<FieldId, NumericValue>,
+    pub keyword_fields: FnvHashMap<FieldId, Vec<TermId>>,
+
+    // The ancestor chain of each hierarchical field, starting with the root
+    // The last value of the chain should also be in keyword_fields
+    pub hierarchy_fields: FnvHashMap<FieldId, Vec<TermId>>,
+
+    // The inputs of completion fields with their weights
+    pub completion_fields: FnvHashMap<FieldId, Vec<(String, f32)>>,
+
+    // The byte range in the original text of each token position, for text fields that have offsets
+    pub offsets: FnvHashMap<FieldId, FnvHashMap<usize, (usize, usize)>>,
+
+    // The original values of stored fields
+    #[serde(with = "persistence::json_map")]
+    pub stored_fields: FnvHashMap<FieldId, serde_json::Value>,
+
+    // The document that was sent to the server, if the database is configured to keep it
+    #[serde(with = "persistence::json_option")]
+    pub source: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct InvertedIndex {
     pub postings: FnvHashMap<TermId, Vec<(DocumentId, FnvHashSet<usize>, f32)>>,
     pub total_documents: usize,
@@ -83,7 +122,11 @@ impl InvertedIndex {
     }
 
     fn calculate_normalizer(&self, term: TermId) -> f32 {
-        let inverse_document_frequency = 1.0 / (self.term_document_frequency(term) as f32 + 1.0).log2();
+        self.calculate_normalizer_with_document_frequency(self.term_document_frequency(term))
+    }
+
+    fn calculate_normalizer_with_document_frequency(&self, document_frequency: usize) -> f32 {
+        let inverse_document_frequency = 1.0 / (document_frequency as f32 + 1.0).log2();
         let field_length_normalizer = 1.0 / (self.total_documents as f32 / self.total_terms as f32);
         inverse_document_frequency * field_length_normalizer
     }
@@ -93,6 +136,13 @@ impl InvertedIndex {
         self.postings.get(&term).map(|postings_list| postings_list.iter().map(|posting| (posting.0, posting.2 * normalizer)).collect()).unwrap_or_default()
     }
 
+    // Like search, but scores using a document frequency that was worked out across multiple fields
+    // This keeps IDF consistent when the same term is searched in several fields at once
+    pub fn blended_search(&self, term: TermId, document_frequency: usize) -> Vec<(DocumentId, f32)> {
+        let normalizer = self.calculate_normalizer_with_document_frequency(document_frequency);
+        self.postings.get(&term).map(|postings_list| postings_list.iter().map(|posting| (posting.0, posting.2 * normalizer)).collect()).unwrap_or_default()
+    }
+
     pub fn phrase_search(&self, terms: &Vec<TermId>) -> Vec<(DocumentId, f32)> {
         // Get posting list for each term. Only continue if all terms have a posting list
         let posting_lists = match terms.into_iter().map(|term| self.postings.get(term).map(|posting_list| (term, posting_list))).collect::<Option<Vec<_>>>() {
@@ -129,12 +179,33 @@ impl InvertedIndex {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct DatabaseStats {
+    pub documents: usize,
+    pub deleted_documents: usize,
+    pub terms: usize,
+
+    // The number of live documents that have a value in each field
+    pub fields: HashMap<String, usize>,
+}
+
+#[derive(Debug, Default, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct Database {
     next_document_id: u32,
     pub term_dictionary: TermDictionary,
     pub data_dictionary: DataDictionary,
     pub fields: FnvHashMap<FieldId, InvertedIndex>,
+    pub numeric_fields: FnvHashMap<FieldId, NumericIndex>,
+    pub keyword_fields: FnvHashMap<FieldId, KeywordIndex>,
+    pub hierarchy_fields: FnvHashMap<FieldId, HierarchyIndex>,
+    pub completion_fields: FnvHashMap<FieldId, CompletionIndex>,
+
+    // The documents that have a value in each field, in the order they were inserted
+    pub field_presence: FnvHashMap<FieldId, Vec<DocumentId>>,
+
+    // Keep the source of each document so that it can be returned with search results
+    pub store_source: bool,
+
     pub docs: FnvHashMap<DocumentId, Document>,
     pub deleted_docs: FnvHashSet<DocumentId>,
     pub pk_to_id: HashMap<String, DocumentId>,
@@ -148,6 +219,39 @@ impl Database {
         for (field_id, tsvector) in &doc.fields {
             let field = self.fields.entry(*field_id).or_default();
             field.insert_tsvector(id, tsvector);
+
+            for term in tsvector.terms.keys() {
+                self.term_dictionary.add_to_field(*field_id, *term);
+            }
+        }
+        for (field_id, value) in &doc.numeric_fields {
+            let field = self.numeric_fields.entry(*field_id).or_default();
+            field.insert(id, *value);
+        }
+        for (field_id, terms) in &doc.keyword_fields {
+            let field = self.keyword_fields.entry(*field_id).or_default();
+            field.insert(id, terms);
+
+            for term in terms {
+                self.term_dictionary.add_to_field(*field_id, *term);
+            }
+        }
+        for (field_id, chain) in &doc.hierarchy_fields {
+            let field = self.hierarchy_fields.entry(*field_id).or_default();
+            field.insert(chain);
+
+            for term in chain {
+                self.term_dictionary.add_to_field(*field_id, *term);
+            }
+        }
+        for (field_id, inputs) in &doc.completion_fields {
+            let field = self.completion_fields.entry(*field_id).or_default();
+            for (input, weight) in inputs {
+                field.insert(id, input, *weight);
+            }
+        }
+        for field_id in doc.fields.keys().chain(doc.numeric_fields.keys()).chain(doc.keyword_fields.keys()).chain(doc.completion_fields.keys()) {
+            self.field_presence.entry(*field_id).or_default().push(id);
         }
         self.docs.insert(id, doc);
         // TODO: Check if PK is taken
@@ -156,13 +260,123 @@ impl Database {
         id
     }
 
+    // Inserts the document, replacing any existing document with the same PK
+    // Also returns whether a new document was created
+    pub fn upsert_document(&mut self, pk: String, doc: Document) -> (DocumentId, bool) {
+        let existing_document_id = self.pk_to_id.get(&pk).cloned();
+        if let Some(existing_document_id) = existing_document_id {
+            self.delete_document(existing_document_id);
+        }
+
+        (self.insert_document(pk, doc), existing_document_id.is_none())
+    }
+
+    pub fn get_document_by_pk(&self, pk: &str) -> Option<(DocumentId, &Document)> {
+        let document_id = *self.pk_to_id.get(pk)?;
+
+        if self.deleted_docs.contains(&document_id) {
+            return None;
+        }
+
+        self.docs.get(&document_id).map(|doc| (document_id, doc))
+    }
+
+    // Finds a document by its id. Deleted documents aren't returned
+    pub fn get_document(&self, document_id: DocumentId) -> Result<&Document, Error> {
+        if self.deleted_docs.contains(&document_id) {
+            return Err(Error::DocumentNotFound { document_id });
+        }
+
+        self.docs.get(&document_id).ok_or(Error::DocumentNotFound { document_id })
+    }
+
+    pub fn get_pk(&self, document_id: DocumentId) -> Result<&str, Error> {
+        self.id_to_pk.get(&document_id).map(|pk| pk.as_str()).ok_or(Error::DocumentNotFound { document_id })
+    }
+
+    // Finds the highest weighted inputs of a completion field that start with the prefix
+    pub fn complete(&self, field_id: FieldId, prefix: &str, size: usize, fuzziness: usize) -> Vec<&CompletionEntry> {
+        self.completion_fields.get(&field_id).map(|field| field.complete(prefix, size, fuzziness)).unwrap_or_default()
+    }
+
+    // Finds a value of a hierarchical field and all of the values below it
+    pub fn descendants(&self, field_id: FieldId, term: TermId) -> Vec<TermId> {
+        self.hierarchy_fields.get(&field_id).map(|field| field.descendants(term)).unwrap_or_else(|| vec![term])
+    }
+
+    pub fn stats(&self) -> DatabaseStats {
+        let live_documents = |document_ids: &Vec<DocumentId>| document_ids.iter().filter(|document_id| !self.deleted_docs.contains(document_id)).count();
+
+        DatabaseStats {
+            documents: self.docs.len().saturating_sub(self.deleted_docs.len()),
+            deleted_documents: self.deleted_docs.len(),
+            terms: self.term_dictionary.terms.len(),
+            fields: self.field_presence.iter().filter_map(|(field_id, document_ids)| {
+                Some((self.data_dictionary.get_name(*field_id)?.to_owned(), live_documents(document_ids)))
+            }).collect(),
+        }
+    }
+
+    // Deletes all documents, keeping the fields and settings
+    pub fn reset(&mut self) {
+        *self = Database {
+            data_dictionary: std::mem::take(&mut self.data_dictionary),
+            store_source: self.store_source,
+            ..Database::default()
+        };
+    }
+
     pub fn delete_document(&mut self, document_id: DocumentId) {
         self.deleted_docs.insert(document_id);
+        for field in self.completion_fields.values_mut() {
+            field.delete(document_id);
+        }
         if let Some(pk) = self.id_to_pk.remove(&document_id) {
             self.pk_to_id.remove(&pk);
         }
     }
 
+    // Returns the id of the document that was deleted, if there was one with the PK
+    pub fn delete_document_by_pk(&mut self, pk: &str) -> Option<DocumentId> {
+        let document_id = *self.pk_to_id.get(pk)?;
+        self.delete_document(document_id);
+        Some(document_id)
+    }
+
+    // Deletes every document that matches the query and returns how many there were
+    pub fn delete_by_query(&mut self, query: &Query) -> usize {
+        let document_ids = self.simple_match(query);
+        for document_id in &document_ids {
+            self.delete_document(*document_id);
+        }
+        document_ids.len()
+    }
+
+    // Finds documents containing the term in either a text or keyword field
+    fn docs_with_term(&self, field_id: FieldId, term_id: TermId) -> Vec<DocumentId> {
+        let document_ids = if let Some(field) = self.fields.get(&field_id) {
+            field.docs_with_term(term_id)
+        } else if let Some(field) = self.keyword_fields.get(&field_id) {
+            field.docs_with_term(term_id)
+        } else {
+            Vec::new()
+        };
+
+        document_ids.into_iter().filter(|document_id| !self.deleted_docs.contains(document_id)).collect()
+    }
+
+    fn search_term(&self, field_id: FieldId, term_id: TermId) -> Vec<(DocumentId, f32)> {
+        let results = if let Some(field) = self.fields.get(&field_id) {
+            field.search(term_id)
+        } else if let Some(field) = self.keyword_fields.get(&field_id) {
+            field.search(term_id)
+        } else {
+            Vec::new()
+        };
+
+        results.into_iter().filter(|(document_id, _)| !self.deleted_docs.contains(document_id)).collect()
+    }
+
     pub fn simple_match(&self, query: &Query) -> Vec<DocumentId> {
         match query {
             Query::MatchAll => {
@@ -172,11 +386,16 @@ impl Database {
                 Vec::new()
             }
             Query::Term(field_id, term_id) => {
-                if let Some(field) = self.fields.get(field_id) {
-                    field.docs_with_term(*term_id).into_iter().filter(|document_id| !self.deleted_docs.contains(document_id)).collect()
-                } else {
-                    Vec::new()
+                self.docs_with_term(*field_id, *term_id)
+            }
+            Query::TermSet(field_id, term_ids) => {
+                let mut results: FnvHashSet<DocumentId> = FnvHashSet::default();
+
+                for term_id in term_ids {
+                    results.extend(self.docs_with_term(*field_id, *term_id));
                 }
+
+                results.into_iter().collect()
             }
             Query::Phrase(field_id, terms) => {
                 if let Some(field) = self.fields.get(field_id) {
@@ -185,6 +404,20 @@ impl Database {
                     Vec::new()
                 }
             }
+            Query::Exists(field_id) => {
+                if let Some(document_ids) = self.field_presence.get(field_id) {
+                    document_ids.iter().filter(|document_id| !self.deleted_docs.contains(document_id)).cloned().collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            Query::Range { field, gte, lte, gt, lt } => {
+                if let Some(field) = self.numeric_fields.get(field) {
+                    field.docs_in_range(*gte, *lte, *gt, *lt).into_iter().filter(|document_id| !self.deleted_docs.contains(document_id)).collect()
+                } else {
+                    Vec::new()
+                }
+            }
             Query::Or(queries) => {
                 let mut results: FnvHashSet<DocumentId> = FnvHashSet::default();
 
@@ -196,6 +429,32 @@ impl Database {
 
                 results.into_iter().collect()
             }
+            Query::DisMax(queries) => {
+                let mut results: FnvHashSet<DocumentId> = FnvHashSet::default();
+
+                for query in queries {
+                    for document_id in self.simple_match(&query) {
+                        results.insert(document_id);
+                    }
+                }
+
+                results.into_iter().collect()
+            }
+            Query::BlendedTerm(fields, term_id) => {
+                let mut results: FnvHashSet<DocumentId> = FnvHashSet::default();
+
+                for (field_id, _) in fields {
+                    if let Some(field) = self.fields.get(field_id) {
+                        for document_id in field.docs_with_term(*term_id) {
+                            if !self.deleted_docs.contains(&document_id) {
+                                results.insert(document_id);
+                            }
+                        }
+                    }
+                }
+
+                results.into_iter().collect()
+            }
             Query::And(queries) => {
                 let mut results: FnvHashMap<DocumentId, usize> = FnvHashMap::default();
 
@@ -219,6 +478,48 @@ impl Database {
             Query::Boost(query, _boost) => {
                 self.simple_match(&query)
             }
+            Query::ConstantScore(query, _score) => {
+                self.simple_match(&query)
+            }
+        }
+    }
+
+    // Counts the documents that match the query without scoring or sorting them
+    // Common queries are counted straight from the indexes, everything else falls back to simple_match
+    pub fn count(&self, query: &Query) -> usize {
+        let is_live = |document_id: &DocumentId| !self.deleted_docs.contains(document_id);
+
+        match query {
+            Query::MatchAll => {
+                self.docs.len().saturating_sub(self.deleted_docs.len())
+            }
+            Query::MatchNone => {
+                0
+            }
+            Query::Term(field_id, term_id) => {
+                if let Some(field) = self.fields.get(field_id) {
+                    field.postings.get(term_id).map(|postings_list| postings_list.iter().filter(|posting| is_live(&posting.0)).count()).unwrap_or(0)
+                } else if let Some(field) = self.keyword_fields.get(field_id) {
+                    field.postings.get(term_id).map(|postings_list| postings_list.iter().filter(|document_id| is_live(document_id)).count()).unwrap_or(0)
+                } else {
+                    0
+                }
+            }
+            Query::Exists(field_id) => {
+                self.field_presence.get(field_id).map(|document_ids| document_ids.iter().filter(|document_id| is_live(document_id)).count()).unwrap_or(0)
+            }
+            Query::Range { field, gte, lte, gt, lt } => {
+                self.numeric_fields.get(field).map(|field| field.docs_in_range(*gte, *lte, *gt, *lt).iter().filter(|document_id| is_live(document_id)).count()).unwrap_or(0)
+            }
+            Query::Exclude(query, filter) if **query == Query::MatchAll => {
+                self.count(query).saturating_sub(self.count(filter))
+            }
+            Query::Boost(query, _) | Query::ConstantScore(query, _) => {
+                self.count(query)
+            }
+            _ => {
+                self.simple_match(query).len()
+            }
         }
     }
 
@@ -231,11 +532,18 @@ impl Database {
                 Vec::new()
             }
             Query::Term(field_id, term_id) => {
-                if let Some(field) = self.fields.get(field_id) {
-                    field.search(*term_id).into_iter().filter(|(document_id, _)| !self.deleted_docs.contains(document_id)).collect()
-                } else {
-                    Vec::new()
+                self.search_term(*field_id, *term_id)
+            }
+            Query::TermSet(field_id, term_ids) => {
+                let mut results: FnvHashMap<DocumentId, f32> = FnvHashMap::default();
+
+                for term_id in term_ids {
+                    for (document_id, score) in self.search_term(*field_id, *term_id) {
+                        *results.entry(document_id).or_default() += score;
+                    }
                 }
+
+                results.into_iter().collect()
             }
             Query::Phrase(field_id, terms) => {
                 if let Some(field) = self.fields.get(field_id) {
@@ -244,6 +552,10 @@ impl Database {
                     Vec::new()
                 }
             }
+            Query::Exists(..) | Query::Range { .. } => {
+                // These are only used for filtering, so they don't contribute to the score
+                self.simple_match(query).into_iter().map(|document_id| (document_id, 0.0)).collect()
+            }
             Query::Or(queries) => {
                 let mut results: FnvHashMap<DocumentId, f32> = FnvHashMap::default();
 
@@ -255,6 +567,38 @@ impl Database {
 
                 results.into_iter().collect()
             }
+            Query::DisMax(queries) => {
+                let mut results: FnvHashMap<DocumentId, f32> = FnvHashMap::default();
+
+                for query in queries {
+                    for (document_id, score) in self.query(&query) {
+                        let result = results.entry(document_id).or_insert(score);
+                        *result = result.max(score);
+                    }
+                }
+
+                results.into_iter().collect()
+            }
+            Query::BlendedTerm(fields, term_id) => {
+                // Use the highest document frequency of any of the fields so that the term is given the same IDF in all of them
+                let document_frequency = fields.iter().filter_map(|(field_id, _)| self.fields.get(field_id)).map(|field| field.term_document_frequency(*term_id)).max().unwrap_or(0);
+                let mut results: FnvHashMap<DocumentId, f32> = FnvHashMap::default();
+
+                for (field_id, boost) in fields {
+                    if let Some(field) = self.fields.get(field_id) {
+                        for (document_id, score) in field.blended_search(*term_id, document_frequency) {
+                            if self.deleted_docs.contains(&document_id) {
+                                continue;
+                            }
+
+                            let result = results.entry(document_id).or_insert(score * boost);
+                            *result = result.max(score * boost);
+                        }
+                    }
+                }
+
+                results.into_iter().collect()
+            }
             Query::And(queries) => {
                 #[derive(Default)]
                 struct Result {
@@ -321,12 +665,214 @@ impl Database {
                 results.into_iter().filter(|(_, result)| !result.passed_filter).map(|(document_id, result)| (document_id, result.score)).collect()
             }
             Query::Boost(query, boost) => {
-                if *boost == 0.0 {
-                    self.simple_match(&query).into_iter().map(|document_id| (document_id, 0.0)).collect()
-                } else {
-                    self.query(&query).into_iter().map(|(document_id, score)| (document_id, score * boost)).collect()
-                }
+                self.query(&query).into_iter().map(|(document_id, score)| (document_id, score * boost)).collect()
+            }
+            Query::ConstantScore(query, score) => {
+                self.simple_match(&query).into_iter().map(|document_id| (document_id, *score)).collect()
+            }
+        }
+    }
+
+    // Reads the value that a document would be sorted by for a field
+    // Keyword fields can have multiple values, the lowest is used when sorting ascending and the highest when descending
+    // Text fields have no doc values (see FieldType::is_sortable), so this is always None for them
+    pub fn sort_value(&self, field_id: FieldId, document_id: DocumentId, order: SortOrder) -> Option<SortValue<'_>> {
+        if let Some(field) = self.numeric_fields.get(&field_id) {
+            return field.doc_value(document_id).map(|value| match value {
+                NumericValue::Integer(value) => SortValue::Integer(value),
+                NumericValue::Float(value) => SortValue::Number(value),
+            });
+        }
+
+        if let Some(field) = self.keyword_fields.get(&field_id) {
+            let values = field.doc_values(document_id).iter().filter_map(|term_id| self.term_dictionary.term_ids.get(term_id)).map(|term| term.as_str());
+
+            return match order {
+                SortOrder::Asc => values.min(),
+                SortOrder::Desc => values.max(),
+            }.map(SortValue::Text);
+        }
+
+        None
+    }
+
+    // Runs the query and sorts the results. If no sort is given, results are sorted by score
+    pub fn search(&self, query: &Query, sort: &[Sort]) -> Vec<(DocumentId, f32)> {
+        self.search_with_sort_values(query, sort).into_iter().map(|(document_id, score, _)| (document_id, score)).collect()
+    }
+
+    // Like search, but also returns the values that each result was sorted by
+    // These can be used to merge the results of searching several databases with the same sort
+    pub fn search_with_sort_values(&self, query: &Query, sort: &[Sort]) -> Vec<(DocumentId, f32, Vec<Option<SortValue<'_>>>)> {
+        let default_sort = [Sort::score()];
+        let sort = if sort.is_empty() { &default_sort[..] } else { sort };
+
+        // Look up the values to sort by up front rather than on every comparison
+        let mut results = self.query(query).into_iter().map(|(document_id, score)| {
+            let values = sort.iter().map(|sort| match sort.key {
+                SortKey::Score => Some(SortValue::Number(score as f64)),
+                SortKey::Field(field_id) => self.sort_value(field_id, document_id, sort.order),
+            }).collect::<Vec<_>>();
+
+            (document_id, score, values)
+        }).collect::<Vec<_>>();
+
+        results.sort_by(|a, b| {
+            sort::compare_all(&a.2, &b.2, sort)
+                // Fall back to insertion order so that results are stable between requests
+                .then_with(|| (a.0).0.cmp(&(b.0).0))
+        });
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Database, Document, DocumentId};
+    use crate::data_dictionary::{FieldConfig, FieldType};
+    use crate::query::Query;
+    use crate::sort::{Sort, SortOrder, MissingOrder};
+    use crate::numeric_index::NumericValue;
+    use crate::tsvector::{TSVector, TSVectorTerm};
+
+    #[test]
+    fn test_delete_document_by_pk() {
+        let mut db = Database::default();
+        let first = db.insert_document("1".to_owned(), Document::default());
+        let second = db.insert_document("2".to_owned(), Document::default());
+
+        assert_eq!(db.delete_document_by_pk("1"), Some(first));
+        assert!(db.get_document_by_pk("1").is_none());
+        assert!(db.get_document(first).is_err());
+        assert_eq!(db.simple_match(&Query::match_all()), vec![second]);
+
+        // Deleting again, or deleting a PK that never existed, does nothing
+        assert_eq!(db.delete_document_by_pk("1"), None);
+        assert_eq!(db.delete_document_by_pk("3"), None);
+        assert_eq!(db.stats().documents, 1);
+
+        // The PK can be reused by a new document
+        let (third, created) = db.upsert_document("1".to_owned(), Document::default());
+        assert!(created);
+        assert_eq!(db.get_document_by_pk("1").map(|(document_id, _)| document_id), Some(third));
+    }
+
+    #[test]
+    fn test_delete_by_query() {
+        let mut db = Database::default();
+        let tags = db.data_dictionary.insert("tags".to_owned(), FieldConfig::default().field_type(FieldType::Keyword));
+        let red = db.term_dictionary.get_or_insert("red");
+
+        for pk in 0..5 {
+            let mut doc = Document::default();
+            if pk % 2 == 0 {
+                doc.keyword_fields.insert(tags, vec![red]);
+            }
+            db.insert_document(pk.to_string(), doc);
+        }
+
+        assert_eq!(db.delete_by_query(&Query::term(tags, red)), 3);
+        assert!(db.get_document_by_pk("0").is_none());
+        assert!(db.get_document_by_pk("1").is_some());
+
+        let mut remaining = db.simple_match(&Query::match_all());
+        remaining.sort_by_key(|document_id| document_id.0);
+        assert_eq!(remaining, vec![DocumentId(1), DocumentId(3)]);
+
+        // Documents that were already deleted aren't counted again
+        assert_eq!(db.delete_by_query(&Query::term(tags, red)), 0);
+        assert_eq!(db.delete_by_query(&Query::match_none()), 0);
+        assert_eq!(db.delete_by_query(&Query::match_all()), 2);
+        assert_eq!(db.stats().documents, 0);
+    }
+
+    fn pks(db: &Database, results: Vec<(DocumentId, f32)>) -> Vec<&str> {
+        results.into_iter().map(|(document_id, _)| db.get_pk(document_id).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_search_sort() {
+        let mut db = Database::default();
+        let name = db.data_dictionary.insert("name".to_owned(), FieldConfig::default().field_type(FieldType::Keyword));
+        let rating = db.data_dictionary.insert("rating".to_owned(), FieldConfig::default().field_type(FieldType::Integer));
+
+        for (pk, name_value, rating_value) in &[("a", "cherry", Some(3)), ("b", "apple", Some(5)), ("c", "banana", None), ("d", "apple", Some(1))] {
+            let mut doc = Document::default();
+            doc.keyword_fields.insert(name, vec![db.term_dictionary.get_or_insert(name_value)]);
+            if let Some(rating_value) = rating_value {
+                doc.numeric_fields.insert(rating, NumericValue::Integer(*rating_value));
             }
+            db.insert_document((*pk).to_owned(), doc);
         }
+
+        assert_eq!(pks(&db, db.search(&Query::match_all(), &[Sort::field(rating, SortOrder::Desc)])), vec!["b", "a", "d", "c"]);
+        assert_eq!(pks(&db, db.search(&Query::match_all(), &[Sort::field(rating, SortOrder::Asc)])), vec!["d", "a", "b", "c"]);
+        assert_eq!(pks(&db, db.search(&Query::match_all(), &[Sort { missing: MissingOrder::First, ..Sort::field(rating, SortOrder::Asc) }])), vec!["c", "d", "a", "b"]);
+
+        // Ties on the first sort are broken by the next one, then by insertion order
+        assert_eq!(pks(&db, db.search(&Query::match_all(), &[Sort::field(name, SortOrder::Asc), Sort::field(rating, SortOrder::Asc)])), vec!["d", "b", "c", "a"]);
+        assert_eq!(pks(&db, db.search(&Query::match_all(), &[Sort::field(name, SortOrder::Asc)])), vec!["b", "d", "c", "a"]);
+
+        // Deleted documents aren't returned
+        db.delete_document_by_pk("b");
+        assert_eq!(pks(&db, db.search(&Query::match_all(), &[Sort::field(rating, SortOrder::Desc)])), vec!["a", "d", "c"]);
+    }
+
+    #[test]
+    fn test_count() {
+        let mut db = Database::default();
+        let body = db.data_dictionary.insert("body".to_owned(), FieldConfig::default());
+        let tags = db.data_dictionary.insert("tags".to_owned(), FieldConfig::default().field_type(FieldType::Keyword));
+        let rating = db.data_dictionary.insert("rating".to_owned(), FieldConfig::default().field_type(FieldType::Integer));
+        let hello = db.term_dictionary.get_or_insert("hello");
+        let red = db.term_dictionary.get_or_insert("red");
+
+        for pk in 0..10 {
+            let mut doc = Document::default();
+            if pk % 2 == 0 {
+                let mut terms = fnv::FnvHashMap::default();
+                terms.insert(hello, TSVectorTerm { positions: vec![1], weight: 1.0 });
+                doc.fields.insert(body, TSVector { length: 1, terms });
+            }
+            if pk % 3 == 0 {
+                doc.keyword_fields.insert(tags, vec![red]);
+            }
+            if pk % 4 != 0 {
+                doc.numeric_fields.insert(rating, NumericValue::Integer(pk));
+            }
+            db.insert_document(pk.to_string(), doc);
+        }
+
+        // Deleted documents, and the old versions of upserted documents, must not be counted
+        db.delete_document_by_pk("0");
+        db.delete_document_by_pk("3");
+        db.upsert_document("6".to_owned(), Document::default());
+        db.upsert_document("7".to_owned(), Document::default());
+
+        let queries = vec![
+            Query::MatchAll,
+            Query::MatchNone,
+            Query::Term(body, hello),
+            Query::Term(tags, red),
+            Query::Term(tags, hello),
+            Query::Exists(body),
+            Query::Exists(tags),
+            Query::Exists(rating),
+            Query::Range { field: rating, gte: Some(NumericValue::Integer(2)), lte: None, gt: None, lt: Some(NumericValue::Integer(9)) },
+            Query::Exclude(Box::new(Query::MatchAll), Box::new(Query::Term(tags, red))),
+            Query::Exclude(Box::new(Query::Exists(rating)), Box::new(Query::Term(body, hello))),
+            Query::Boost(Box::new(Query::Term(body, hello)), 2.0),
+            Query::ConstantScore(Box::new(Query::Exists(tags)), 1.0),
+            Query::Or(vec![Query::Term(body, hello), Query::Term(tags, red)]),
+            Query::And(vec![Query::Term(body, hello), Query::Exists(rating)]),
+        ];
+
+        for query in &queries {
+            assert_eq!(db.count(query), db.simple_match(query).len(), "{:?}", query);
+        }
+
+        assert_eq!(db.count(&Query::MatchAll), 8);
+        assert_eq!(db.count(&Query::Term(tags, red)), 1);
     }
 }
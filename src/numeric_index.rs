@@ -0,0 +1,167 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::ops::Bound;
+
+use super::DocumentId;
+
+// The value of a numeric or date field
+// Integers (and dates, as milliseconds) are kept as i64 so that they don't lose precision above 2^53
+#[derive(Debug, Clone, Copy, serde_derive::Serialize, serde_derive::Deserialize)]
+pub enum NumericValue {
+    Integer(i64),
+    Float(f64),
+}
+
+impl NumericValue {
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            NumericValue::Integer(value) => *value as f64,
+            NumericValue::Float(value) => *value,
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        match self {
+            NumericValue::Integer(_) => false,
+            NumericValue::Float(value) => value.is_nan(),
+        }
+    }
+}
+
+// Compares an integer with a float without rounding either of them
+fn compare_integer_float(integer: i64, float: f64) -> Ordering {
+    // 2^63, the first float that is too large for an i64
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+    if float.is_nan() || float >= LIMIT {
+        return Ordering::Less;
+    }
+    if float < -LIMIT {
+        return Ordering::Greater;
+    }
+
+    let whole = float.trunc();
+    integer.cmp(&(whole as i64)).then_with(|| 0.0.partial_cmp(&(float - whole)).unwrap_or(Ordering::Equal))
+}
+
+// Values are ordered numerically, even when comparing an integer with a float
+// NaN is ordered after every other value, but it is never indexed
+impl Ord for NumericValue {
+    fn cmp(&self, other: &NumericValue) -> Ordering {
+        match (self, other) {
+            (NumericValue::Integer(a), NumericValue::Integer(b)) => a.cmp(b),
+            (NumericValue::Float(a), NumericValue::Float(b)) => a.partial_cmp(b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan())),
+            (NumericValue::Integer(a), NumericValue::Float(b)) => compare_integer_float(*a, *b),
+            (NumericValue::Float(a), NumericValue::Integer(b)) => compare_integer_float(*b, *a).reverse(),
+        }
+    }
+}
+
+impl PartialOrd for NumericValue {
+    fn partial_cmp(&self, other: &NumericValue) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for NumericValue {
+    fn eq(&self, other: &NumericValue) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for NumericValue {}
+
+#[derive(Debug, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct NumericIndex {
+    // Ordered by value so that ranges can be found without scanning the whole field
+    pub values: BTreeSet<(NumericValue, DocumentId)>,
+
+    // The value of each document, indexed by document id. Used for sorting
+    pub doc_values: Vec<Option<NumericValue>>,
+}
+
+impl NumericIndex {
+    pub fn insert(&mut self, document_id: DocumentId, value: NumericValue) {
+        self.values.insert((value, document_id));
+
+        let index = document_id.0 as usize;
+        if self.doc_values.len() <= index {
+            self.doc_values.resize(index + 1, None);
+        }
+        self.doc_values[index] = Some(value);
+    }
+
+    pub fn doc_value(&self, document_id: DocumentId) -> Option<NumericValue> {
+        self.doc_values.get(document_id.0 as usize).cloned().flatten()
+    }
+
+    pub fn docs_in_range(&self, gte: Option<NumericValue>, lte: Option<NumericValue>, gt: Option<NumericValue>, lt: Option<NumericValue>) -> Vec<DocumentId> {
+        // Start at the first document with the higher of the lower bounds, then skip over any that are equal to "gt"
+        let start = match gte.into_iter().chain(gt).max() {
+            Some(start) => Bound::Included((start, DocumentId(0))),
+            None => Bound::Unbounded,
+        };
+
+        self.values.range((start, Bound::Unbounded))
+            .skip_while(|(value, _)| gt.is_some_and(|gt| *value <= gt))
+            .take_while(|(value, _)| lte.is_none_or(|lte| *value <= lte) && lt.is_none_or(|lt| *value < lt))
+            .map(|(_, document_id)| *document_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::DocumentId;
+    use super::{NumericIndex, NumericValue};
+
+    fn float(value: f64) -> Option<NumericValue> {
+        Some(NumericValue::Float(value))
+    }
+
+    #[test]
+    fn test_docs_in_range() {
+        let mut index = NumericIndex::default();
+        index.insert(DocumentId(0), NumericValue::Float(30.0));
+        index.insert(DocumentId(1), NumericValue::Float(10.0));
+        index.insert(DocumentId(2), NumericValue::Float(20.0));
+        index.insert(DocumentId(3), NumericValue::Float(20.0));
+
+        assert_eq!(index.docs_in_range(float(20.0), None, None, None), vec![DocumentId(2), DocumentId(3), DocumentId(0)]);
+        assert_eq!(index.docs_in_range(None, None, float(20.0), None), vec![DocumentId(0)]);
+        assert_eq!(index.docs_in_range(None, float(20.0), None, None), vec![DocumentId(1), DocumentId(2), DocumentId(3)]);
+        assert_eq!(index.docs_in_range(None, None, None, float(20.0)), vec![DocumentId(1)]);
+        assert_eq!(index.docs_in_range(float(15.0), float(25.0), None, None), vec![DocumentId(2), DocumentId(3)]);
+        assert_eq!(index.docs_in_range(float(10.0), None, float(20.0), None), vec![DocumentId(0)]);
+
+        // Empty ranges
+        assert_eq!(index.docs_in_range(float(25.0), float(15.0), None, None), vec![]);
+        assert_eq!(index.docs_in_range(None, None, float(20.0), float(20.0)), vec![]);
+    }
+
+    #[test]
+    fn test_integers_keep_precision() {
+        let mut index = NumericIndex::default();
+        index.insert(DocumentId(0), NumericValue::Integer(9_007_199_254_740_993));
+        index.insert(DocumentId(1), NumericValue::Integer(9_007_199_254_740_992));
+
+        // These are the same number as floats
+        assert_eq!(index.docs_in_range(Some(NumericValue::Integer(9_007_199_254_740_993)), None, None, None), vec![DocumentId(0)]);
+        assert_eq!(index.doc_value(DocumentId(0)), Some(NumericValue::Integer(9_007_199_254_740_993)));
+
+        // Float bounds are compared exactly
+        assert_eq!(index.docs_in_range(None, None, float(9_007_199_254_740_992.0), None), vec![DocumentId(0)]);
+        assert_eq!(index.docs_in_range(None, float(9_007_199_254_740_992.0), None, None), vec![DocumentId(1)]);
+    }
+
+    #[test]
+    fn test_compare_values() {
+        assert_eq!(NumericValue::Integer(2).cmp(&NumericValue::Float(2.5)), Ordering::Less);
+        assert_eq!(NumericValue::Integer(-2).cmp(&NumericValue::Float(-2.5)), Ordering::Greater);
+        assert_eq!(NumericValue::Float(3.0).cmp(&NumericValue::Integer(3)), Ordering::Equal);
+        assert_eq!(NumericValue::Integer(i64::MAX).cmp(&NumericValue::Float(1e19)), Ordering::Less);
+        assert_eq!(NumericValue::Integer(i64::MIN).cmp(&NumericValue::Float(-1e19)), Ordering::Greater);
+    }
+}
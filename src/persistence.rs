@@ -0,0 +1,152 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::Database;
+
+// The name of the file that each database is written to, inside its directory
+pub const DATABASE_FILENAME: &str = "database.bin";
+
+#[derive(Debug)]
+pub enum PersistenceError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersistenceError::Io(error) => write!(f, "I/O error: {}", error),
+            PersistenceError::Encoding(error) => write!(f, "encoding error: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for PersistenceError {
+    fn from(error: io::Error) -> PersistenceError {
+        PersistenceError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for PersistenceError {
+    fn from(error: bincode::Error) -> PersistenceError {
+        PersistenceError::Encoding(error)
+    }
+}
+
+// Returns true if a database has been saved into the directory
+pub fn exists(directory: &Path) -> bool {
+    directory.join(DATABASE_FILENAME).is_file()
+}
+
+// Gives each save its own temporary file
+static NEXT_SAVE_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Writes the database into the directory, creating it if needed
+// The database is written to a temporary file first so a crash can't leave a half written file behind
+// Note: saves of the same directory can overlap without corrupting the file, but the last one to finish wins
+pub fn save(db: &Database, directory: &Path) -> Result<(), PersistenceError> {
+    fs::create_dir_all(directory)?;
+
+    let temporary_path = directory.join(format!("{}.{}-{}.tmp", DATABASE_FILENAME, process::id(), NEXT_SAVE_ID.fetch_add(1, Ordering::Relaxed)));
+    let result = write_file(db, &temporary_path).and_then(|_| Ok(fs::rename(&temporary_path, directory.join(DATABASE_FILENAME))?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+
+    result
+}
+
+fn write_file(db: &Database, path: &Path) -> Result<(), PersistenceError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    bincode::serialize_into(&mut writer, db)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok(())
+}
+
+pub fn load(directory: &Path) -> Result<Database, PersistenceError> {
+    let reader = BufReader::new(File::open(directory.join(DATABASE_FILENAME))?);
+    Ok(bincode::deserialize_from(reader)?)
+}
+
+// bincode can't decode serde_json::Value as it isn't a self-describing format
+// These store each value as a JSON string instead
+pub mod json_map {
+    use std::hash::Hash;
+    use fnv::FnvHashMap;
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use serde::de::Error;
+
+    pub fn serialize<K: Serialize + Eq + Hash, S: Serializer>(map: &FnvHashMap<K, serde_json::Value>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(map.iter().map(|(key, value)| (key, value.to_string())))
+    }
+
+    pub fn deserialize<'de, K: Deserialize<'de> + Eq + Hash, D: Deserializer<'de>>(deserializer: D) -> Result<FnvHashMap<K, serde_json::Value>, D::Error> {
+        FnvHashMap::<K, String>::deserialize(deserializer)?.into_iter().map(|(key, value)| {
+            Ok((key, serde_json::from_str(&value).map_err(D::Error::custom)?))
+        }).collect()
+    }
+}
+
+pub mod json_option {
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(value: &Option<serde_json::Value>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.as_ref().map(|value| value.to_string()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<serde_json::Value>, D::Error> {
+        Option::<String>::deserialize(deserializer)?.map(|value| serde_json::from_str(&value).map_err(D::Error::custom)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::{Database, Document};
+    use crate::data_dictionary::{FieldConfig, FieldType};
+    use crate::numeric_index::NumericValue;
+    use super::{save, load, exists, DATABASE_FILENAME};
+
+    #[test]
+    fn test_save_and_load() {
+        let directory = env::temp_dir().join(format!("sparrow-persistence-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let mut db = Database { store_source: true, ..Database::default() };
+        let field = db.data_dictionary.insert("rating".to_owned(), FieldConfig::default().field_type(FieldType::Integer));
+
+        let mut doc = Document::default();
+        doc.numeric_fields.insert(field, NumericValue::Integer(5));
+        doc.stored_fields.insert(field, serde_json::json!(5));
+        doc.source = Some(serde_json::json!({"rating": 5}));
+        db.insert_document("1".to_owned(), doc);
+
+        assert!(!exists(&directory));
+        save(&db, &directory).unwrap();
+        save(&db, &directory).unwrap();
+        assert!(exists(&directory));
+
+        // The temporary files are renamed into place, so they shouldn't be left behind
+        let filenames = fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect::<Vec<_>>();
+        assert_eq!(filenames, vec![DATABASE_FILENAME]);
+
+        let loaded = load(&directory).unwrap();
+        assert!(loaded.store_source);
+        assert_eq!(loaded.data_dictionary.get_by_name("rating").map(|(field_id, _)| field_id), Some(field));
+
+        let (_, doc) = loaded.get_document_by_pk("1").unwrap();
+        assert_eq!(doc.numeric_fields[&field], NumericValue::Integer(5));
+        assert_eq!(doc.stored_fields[&field], serde_json::json!(5));
+        assert_eq!(doc.source, Some(serde_json::json!({"rating": 5})));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
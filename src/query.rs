@@ -1,17 +1,31 @@
 use super::term_dictionary::TermId;
 use super::data_dictionary::FieldId;
+use super::numeric_index::NumericValue;
+use super::error::Error;
 
 #[derive(Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub enum Query {
     MatchAll,
     MatchNone,
     Term(FieldId, TermId),
+    TermSet(FieldId, Vec<TermId>),
     Phrase(FieldId, Vec<TermId>),
+    Exists(FieldId),
+    Range {
+        field: FieldId,
+        gte: Option<NumericValue>,
+        lte: Option<NumericValue>,
+        gt: Option<NumericValue>,
+        lt: Option<NumericValue>,
+    },
     Or(Vec<Query>),
     And(Vec<Query>),
+    DisMax(Vec<Query>),
+    BlendedTerm(Vec<(FieldId, f32)>, TermId),
     Filter(Box<Query>, Box<Query>),
     Exclude(Box<Query>, Box<Query>),
     Boost(Box<Query>, f32),
+    ConstantScore(Box<Query>, f32),
 }
 
 impl Query {
@@ -27,10 +41,33 @@ impl Query {
         Query::Term(field, term)
     }
 
+    // Matches documents that contain any of the terms in the field
+    pub fn term_set(field: FieldId, mut terms: Vec<TermId>) -> Query {
+        match terms.len() {
+            0 => Query::MatchNone,
+            1 => Query::Term(field, terms.pop().unwrap()),
+            _ => Query::TermSet(field, terms),
+        }
+    }
+
     pub fn phrase(field: FieldId, terms: Vec<TermId>) -> Query {
         Query::Phrase(field, terms)
     }
 
+    // Matches documents that have a value in the field
+    pub fn exists(field: FieldId) -> Query {
+        Query::Exists(field)
+    }
+
+    // Matches documents that don't have a value in the field
+    pub fn missing(field: FieldId) -> Query {
+        Query::not(Query::exists(field))
+    }
+
+    pub fn range(field: FieldId, gte: Option<NumericValue>, lte: Option<NumericValue>, gt: Option<NumericValue>, lt: Option<NumericValue>) -> Query {
+        Query::Range { field, gte, lte, gt, lt }
+    }
+
     pub fn or(queries: Vec<Query>) -> Query {
         // Allocate new vec with the assumption that it would be the same size
         let mut processed_queries = Vec::with_capacity(queries.len());
@@ -38,7 +75,7 @@ impl Query {
 
         for query in queries {
             match query {
-                Query::Term(..) | Query::Phrase(..) | Query::And(..) | Query::Filter(..) | Query::Exclude(..) | Query::Boost(..) => processed_queries.push(query),
+                Query::Term(..) | Query::TermSet(..) | Query::Phrase(..) | Query::Exists(..) | Query::Range { .. } | Query::And(..) | Query::DisMax(..) | Query::BlendedTerm(..) | Query::Filter(..) | Query::Exclude(..) | Query::Boost(..) | Query::ConstantScore(..) => processed_queries.push(query),
 
                 // Ignore MatchNone in Or queries
                 Query::MatchNone => {},
@@ -74,7 +111,7 @@ impl Query {
 
         for query in queries {
             match query {
-                Query::Term(..) | Query::Phrase(..) | Query::Or(..) | Query::Filter(..) | Query::Exclude(..) | Query::Boost(..) => processed_queries.push(query),
+                Query::Term(..) | Query::TermSet(..) | Query::Phrase(..) | Query::Exists(..) | Query::Range { .. } | Query::Or(..) | Query::DisMax(..) | Query::BlendedTerm(..) | Query::Filter(..) | Query::Exclude(..) | Query::Boost(..) | Query::ConstantScore(..) => processed_queries.push(query),
 
                 // Ignore everything if there's a MatchNone
                 Query::MatchNone => return Query::MatchNone,
@@ -98,6 +135,26 @@ impl Query {
         }
     }
 
+    // Matches documents that match any of the queries, but scores using the highest scoring query rather than the sum
+    pub fn dis_max(queries: Vec<Query>) -> Query {
+        let mut processed_queries = queries.into_iter().filter(|query| *query != Query::MatchNone).collect::<Vec<_>>();
+
+        match processed_queries.len() {
+            0 => Query::MatchNone,
+            1 => processed_queries.pop().unwrap(),
+            _ => Query::DisMax(processed_queries),
+        }
+    }
+
+    // Searches for a term in multiple fields as if they were one field
+    pub fn blended_term(fields: Vec<(FieldId, f32)>, term: TermId) -> Query {
+        if fields.is_empty() {
+            return Query::MatchNone;
+        }
+
+        Query::BlendedTerm(fields, term)
+    }
+
     pub fn not(query: Query) -> Query {
         Query::exclude(Query::match_all(), query)
     }
@@ -109,6 +166,13 @@ impl Query {
             (_, Query::MatchNone) => Query::match_none(),
             (_, Query::Filter(filter_query, filter)) if **filter_query == Query::MatchAll => Query::filter(query, *filter.clone()),
             (_, Query::Exclude(filter_query, filter)) if **filter_query == Query::MatchAll => Query::exclude(query, *filter.clone()),
+
+            // Filters aren't scored, so there's no point in boosting them
+            (_, Query::Boost(filter, _)) | (_, Query::ConstantScore(filter, _)) => Query::filter(query, *filter.clone()),
+
+            // Nothing is being scored, so avoid running the filter as a scored query
+            (Query::MatchAll, _) => Query::constant_score(filter, 0.0),
+
             _ => Query::Filter(Box::new(query), Box::new(filter)),
         }
     }
@@ -120,12 +184,76 @@ impl Query {
             (_, Query::MatchNone) => query,
             (_, Query::Filter(exclude_query, filter)) if **exclude_query == Query::MatchAll => Query::exclude(query, *filter.clone()),
             (_, Query::Exclude(exclude_query, filter)) if **exclude_query == Query::MatchAll => Query::filter(query, *filter.clone()),
+            (_, Query::Boost(filter, _)) | (_, Query::ConstantScore(filter, _)) => Query::exclude(query, *filter.clone()),
             _ => Query::Exclude(Box::new(query), Box::new(filter)),
         }
     }
 
     pub fn boost(query: Query, boost: f32) -> Query {
-        Query::Boost(Box::new(query), boost)
+        match query {
+            Query::MatchNone => Query::MatchNone,
+            Query::ConstantScore(query, score) => Query::ConstantScore(query, score * boost),
+            _ if boost == 1.0 => query,
+            _ if boost == 0.0 => Query::constant_score(query, 0.0),
+            _ => Query::Boost(Box::new(query), boost),
+        }
+    }
+
+    // Matches the same documents as the inner query, but gives them all the same score
+    pub fn constant_score(query: Query, score: f32) -> Query {
+        match query {
+            Query::MatchNone => Query::MatchNone,
+
+            // The inner query's scores are thrown away so there's no need to keep boosts
+            Query::Boost(query, _) | Query::ConstantScore(query, _) => Query::ConstantScore(query, score),
+
+            _ => Query::ConstantScore(Box::new(query), score),
+        }
+    }
+
+    // Checks for values that would make scores or ranges meaningless, such as boosts that aren't finite numbers
+    // Scores are compared when sorting results, so a NaN in one would make the order of the results undefined
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: String| Err(Error::InvalidQuery { message });
+
+        match self {
+            Query::Range { gte, lte, gt, lt, .. } => {
+                if [gte, lte, gt, lt].iter().any(|bound| bound.map(|bound| bound.is_nan()).unwrap_or(false)) {
+                    return invalid("Range bounds must be numbers".to_owned());
+                }
+            }
+            Query::BlendedTerm(fields, _) => {
+                if let Some((_, boost)) = fields.iter().find(|(_, boost)| !boost.is_finite() || *boost < 0.0) {
+                    return invalid(format!("Field boosts must be positive numbers, got {}", boost));
+                }
+            }
+            Query::Or(queries) | Query::And(queries) | Query::DisMax(queries) => {
+                for query in queries {
+                    query.validate()?;
+                }
+            }
+            Query::Filter(query, filter) | Query::Exclude(query, filter) => {
+                query.validate()?;
+                filter.validate()?;
+            }
+            Query::Boost(query, boost) => {
+                if !boost.is_finite() || *boost < 0.0 {
+                    return invalid(format!("Boosts must be positive numbers, got {}", boost));
+                }
+
+                query.validate()?;
+            }
+            Query::ConstantScore(query, score) => {
+                if !score.is_finite() {
+                    return invalid(format!("Constant scores must be finite numbers, got {}", score));
+                }
+
+                query.validate()?;
+            }
+            Query::MatchAll | Query::MatchNone | Query::Term(..) | Query::TermSet(..) | Query::Phrase(..) | Query::Exists(..) => {}
+        }
+
+        Ok(())
     }
 }
 
@@ -133,6 +261,7 @@ impl Query {
 mod tests {
     use crate::term_dictionary::TermId;
     use crate::data_dictionary::FieldId;
+    use crate::numeric_index::NumericValue;
     use super::Query;
 
     #[test]
@@ -150,11 +279,39 @@ mod tests {
         assert_eq!(Query::term(FieldId(1), TermId(123)), Query::Term(FieldId(1), TermId(123)));
     }
 
+    #[test]
+    fn test_term_set() {
+        assert_eq!(Query::term_set(FieldId(1), vec![TermId(123), TermId(456)]), Query::TermSet(FieldId(1), vec![TermId(123), TermId(456)]));
+
+        // Single term sets should be converted into a Term
+        assert_eq!(Query::term_set(FieldId(1), vec![TermId(123)]), Query::Term(FieldId(1), TermId(123)));
+
+        assert_eq!(Query::term_set(FieldId(1), vec![]), Query::MatchNone);
+    }
+
     #[test]
     fn test_phrase() {
         assert_eq!(Query::phrase(FieldId(1), vec![TermId(123), TermId(456), TermId(789)]), Query::Phrase(FieldId(1),  vec![TermId(123), TermId(456), TermId(789)]));
     }
 
+    #[test]
+    fn test_exists() {
+        assert_eq!(Query::exists(FieldId(1)), Query::Exists(FieldId(1)));
+    }
+
+    #[test]
+    fn test_missing() {
+        assert_eq!(Query::missing(FieldId(1)), Query::Exclude(Box::new(Query::MatchAll), Box::new(Query::Exists(FieldId(1)))));
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(
+            Query::range(FieldId(1), Some(NumericValue::Integer(10)), None, None, Some(NumericValue::Float(20.5))),
+            Query::Range { field: FieldId(1), gte: Some(NumericValue::Integer(10)), lte: None, gt: None, lt: Some(NumericValue::Float(20.5)) }
+        );
+    }
+
     #[test]
     fn test_or() {
         assert_eq!(
@@ -256,6 +413,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_dis_max() {
+        assert_eq!(
+            Query::dis_max(vec![Query::Term(FieldId(1), TermId(123)), Query::Term(FieldId(2), TermId(123))]),
+            Query::DisMax(vec![Query::Term(FieldId(1), TermId(123)), Query::Term(FieldId(2), TermId(123))])
+        );
+
+        // Nested Or queries should not be inlined as they are scored differently
+        assert_eq!(
+            Query::dis_max(vec![Query::Or(vec![Query::Term(FieldId(1), TermId(123)), Query::Term(FieldId(1), TermId(456))]), Query::Term(FieldId(2), TermId(123))]),
+            Query::DisMax(vec![Query::Or(vec![Query::Term(FieldId(1), TermId(123)), Query::Term(FieldId(1), TermId(456))]), Query::Term(FieldId(2), TermId(123))])
+        );
+
+        // MatchNone should be ignored and single element queries should be unwrapped
+        assert_eq!(
+            Query::dis_max(vec![Query::Term(FieldId(1), TermId(123)), Query::MatchNone]),
+            Query::Term(FieldId(1), TermId(123))
+        );
+
+        assert_eq!(
+            Query::dis_max(vec![Query::MatchNone, Query::MatchNone]),
+            Query::MatchNone
+        );
+    }
+
+    #[test]
+    fn test_blended_term() {
+        assert_eq!(
+            Query::blended_term(vec![(FieldId(1), 2.0), (FieldId(2), 1.0)], TermId(123)),
+            Query::BlendedTerm(vec![(FieldId(1), 2.0), (FieldId(2), 1.0)], TermId(123))
+        );
+
+        assert_eq!(
+            Query::blended_term(vec![], TermId(123)),
+            Query::MatchNone
+        );
+    }
+
     #[test]
     fn test_not() {
         assert_eq!(
@@ -275,7 +470,7 @@ mod tests {
 
         assert_eq!(
             Query::not(Query::not(Query::Term(FieldId(1), TermId(123)))),
-            Query::Filter(Box::new(Query::MatchAll), Box::new(Query::Term(FieldId(1), TermId(123))))
+            Query::ConstantScore(Box::new(Query::Term(FieldId(1), TermId(123))), 0.0)
         );
     }
 
@@ -286,9 +481,21 @@ mod tests {
             Query::Filter(Box::new(Query::Term(FieldId(1), TermId(123))), Box::new(Query::Term(FieldId(1), TermId(456))))
         );
 
+        // Filtering MatchAll doesn't score anything
         assert_eq!(
             Query::filter(Query::MatchAll, Query::Term(FieldId(1), TermId(456))),
-            Query::Filter(Box::new(Query::MatchAll), Box::new(Query::Term(FieldId(1), TermId(456))))
+            Query::ConstantScore(Box::new(Query::Term(FieldId(1), TermId(456))), 0.0)
+        );
+
+        // Boosts are removed from filters
+        assert_eq!(
+            Query::filter(Query::Term(FieldId(1), TermId(123)), Query::Boost(Box::new(Query::Term(FieldId(1), TermId(456))), 2.0)),
+            Query::Filter(Box::new(Query::Term(FieldId(1), TermId(123))), Box::new(Query::Term(FieldId(1), TermId(456))))
+        );
+
+        assert_eq!(
+            Query::filter(Query::Term(FieldId(1), TermId(123)), Query::ConstantScore(Box::new(Query::Term(FieldId(1), TermId(456))), 1.0)),
+            Query::Filter(Box::new(Query::Term(FieldId(1), TermId(123))), Box::new(Query::Term(FieldId(1), TermId(456))))
         );
 
         assert_eq!(
@@ -434,5 +641,51 @@ mod tests {
     #[test]
     fn test_boost() {
         assert_eq!(Query::boost(Query::Term(FieldId(1), TermId(123)), 2.0), Query::Boost(Box::new(Query::Term(FieldId(1), TermId(123))), 2.0));
+
+        // Boosting by 1 does nothing
+        assert_eq!(Query::boost(Query::Term(FieldId(1), TermId(123)), 1.0), Query::Term(FieldId(1), TermId(123)));
+
+        // There's nothing to boost in a MatchNone
+        assert_eq!(Query::boost(Query::MatchNone, 2.0), Query::MatchNone);
+
+        // Boosting by 0 means the query doesn't need to be scored
+        assert_eq!(Query::boost(Query::Term(FieldId(1), TermId(123)), 0.0), Query::ConstantScore(Box::new(Query::Term(FieldId(1), TermId(123))), 0.0));
+
+        // Boosting a constant score query changes its score
+        assert_eq!(
+            Query::boost(Query::ConstantScore(Box::new(Query::Term(FieldId(1), TermId(123))), 2.0), 3.0),
+            Query::ConstantScore(Box::new(Query::Term(FieldId(1), TermId(123))), 6.0)
+        );
+    }
+
+    #[test]
+    fn test_constant_score() {
+        assert_eq!(
+            Query::constant_score(Query::Term(FieldId(1), TermId(123)), 1.0),
+            Query::ConstantScore(Box::new(Query::Term(FieldId(1), TermId(123))), 1.0)
+        );
+
+        // Inner boosts are discarded
+        assert_eq!(
+            Query::constant_score(Query::Boost(Box::new(Query::Term(FieldId(1), TermId(123))), 2.0), 1.0),
+            Query::ConstantScore(Box::new(Query::Term(FieldId(1), TermId(123))), 1.0)
+        );
+
+        assert_eq!(
+            Query::constant_score(Query::MatchNone, 1.0),
+            Query::MatchNone
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(Query::boost(Query::Term(FieldId(1), TermId(123)), 2.0).validate(), Ok(()));
+        assert!(Query::boost(Query::Term(FieldId(1), TermId(123)), f32::NAN).validate().is_err());
+        assert!(Query::boost(Query::Term(FieldId(1), TermId(123)), -1.0).validate().is_err());
+        assert!(Query::blended_term(vec![(FieldId(1), 1.0), (FieldId(2), f32::INFINITY)], TermId(123)).validate().is_err());
+        assert!(Query::range(FieldId(1), Some(NumericValue::Float(f64::NAN)), None, None, None).validate().is_err());
+
+        // Invalid queries are found inside other queries
+        assert!(Query::and(vec![Query::Term(FieldId(1), TermId(123)), Query::constant_score(Query::Term(FieldId(2), TermId(123)), f32::NAN)]).validate().is_err());
     }
 }
@@ -0,0 +1,576 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::term_dictionary::TermDictionary;
+use super::data_dictionary::{FieldId, DataDictionary};
+use super::query::Query;
+
+// The maximum number of terms that a prefix (eg. "foo*") can expand to
+const MAX_PREFIX_EXPANSIONS: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    UnclosedPhrase,
+    UnclosedGroup,
+    InvalidBoost(String),
+    UnknownField(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken(token) => write!(f, "unexpected '{}'", token),
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of query"),
+            ParseError::UnclosedPhrase => write!(f, "phrase is missing a closing '\"'"),
+            ParseError::UnclosedGroup => write!(f, "group is missing a closing ')'"),
+            ParseError::InvalidBoost(boost) => write!(f, "invalid boost '{}'", boost),
+            ParseError::UnknownField(field) => write!(f, "unknown field '{}'", field),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Word(String),
+    Prefix(String),
+    Phrase(String),
+    Field(String),
+    Boost(f32),
+    Required,
+    Excluded,
+    And,
+    Or,
+    OpenGroup,
+    CloseGroup,
+}
+
+impl fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lexeme::Word(word) => write!(f, "{}", word),
+            Lexeme::Prefix(prefix) => write!(f, "{}*", prefix),
+            Lexeme::Phrase(phrase) => write!(f, "\"{}\"", phrase),
+            Lexeme::Field(field) => write!(f, "{}:", field),
+            Lexeme::Boost(boost) => write!(f, "^{}", boost),
+            Lexeme::Required => write!(f, "+"),
+            Lexeme::Excluded => write!(f, "-"),
+            Lexeme::And => write!(f, "AND"),
+            Lexeme::Or => write!(f, "OR"),
+            Lexeme::OpenGroup => write!(f, "("),
+            Lexeme::CloseGroup => write!(f, ")"),
+        }
+    }
+}
+
+fn read_word(chars: &mut Peekable<Chars>) -> String {
+    let mut word = String::new();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || c == '(' || c == ')' || c == '"' || c == '^' {
+            break;
+        }
+
+        word.push(c);
+        chars.next();
+    }
+
+    word
+}
+
+fn tokenize(input: &str) -> Result<Vec<Lexeme>, ParseError> {
+    let mut lexemes = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                lexemes.push(Lexeme::OpenGroup);
+            }
+            ')' => {
+                chars.next();
+                lexemes.push(Lexeme::CloseGroup);
+            }
+            // Note: these are only modifiers at the start of a term, so hyphenated words are left alone
+            '+' => {
+                chars.next();
+                lexemes.push(Lexeme::Required);
+            }
+            '-' => {
+                chars.next();
+                lexemes.push(Lexeme::Excluded);
+            }
+            '"' => {
+                chars.next();
+                let mut phrase = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => phrase.push(c),
+                        None => return Err(ParseError::UnclosedPhrase),
+                    }
+                }
+
+                lexemes.push(Lexeme::Phrase(phrase));
+            }
+            '^' => {
+                chars.next();
+                let boost = read_word(&mut chars);
+
+                match boost.parse::<f32>() {
+                    Ok(value) if value.is_finite() && value >= 0.0 => lexemes.push(Lexeme::Boost(value)),
+                    _ => return Err(ParseError::InvalidBoost(boost)),
+                }
+            }
+            _ => {
+                let mut word = read_word(&mut chars);
+
+                if let Some((field, rest)) = word.split_once(':') {
+                    if !field.is_empty() {
+                        lexemes.push(Lexeme::Field(field.to_owned()));
+
+                        // The value might be a phrase or group, in which case it gets picked up on the next iteration
+                        if rest.is_empty() {
+                            continue;
+                        }
+
+                        word = rest.to_owned();
+                    }
+                }
+
+                if word == "AND" {
+                    lexemes.push(Lexeme::And);
+                } else if word == "OR" {
+                    lexemes.push(Lexeme::Or);
+                } else if word.len() > 1 && word.ends_with('*') {
+                    lexemes.push(Lexeme::Prefix(word.trim_end_matches('*').to_owned()));
+                } else {
+                    lexemes.push(Lexeme::Word(word));
+                }
+            }
+        }
+    }
+
+    Ok(lexemes)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Occur {
+    Should,
+    Must,
+    MustNot,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Term(Option<String>, String),
+    Prefix(Option<String>, String),
+    Phrase(Option<String>, String),
+    Group(Option<String>, Vec<(Occur, Node)>),
+    Boost(Box<Node>, f32),
+}
+
+struct Parser {
+    lexemes: Vec<Lexeme>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Lexeme> {
+        let lexeme = self.lexemes.get(self.position).cloned();
+        self.position += 1;
+        lexeme
+    }
+
+    fn parse_clauses(&mut self, in_group: bool) -> Result<Vec<(Occur, Node)>, ParseError> {
+        let mut clauses: Vec<(Occur, Node)> = Vec::new();
+        let mut pending_operator = None;
+
+        loop {
+            match self.peek() {
+                None => {
+                    if in_group {
+                        return Err(ParseError::UnclosedGroup);
+                    }
+
+                    break;
+                }
+                Some(Lexeme::CloseGroup) => {
+                    if !in_group {
+                        return Err(ParseError::UnexpectedToken(")".to_owned()));
+                    }
+
+                    self.next();
+                    break;
+                }
+                Some(Lexeme::And) | Some(Lexeme::Or) => {
+                    let operator = self.next().unwrap();
+
+                    if pending_operator.is_some() {
+                        return Err(ParseError::UnexpectedToken(operator.to_string()));
+                    }
+
+                    // The clause before an AND is required as well as the one after it
+                    match clauses.last_mut() {
+                        Some((occur, _)) => if operator == Lexeme::And && *occur == Occur::Should {
+                            *occur = Occur::Must;
+                        }
+                        None => return Err(ParseError::UnexpectedToken(operator.to_string())),
+                    }
+
+                    pending_operator = Some(operator);
+                }
+                Some(_) => {
+                    let (mut occur, node) = self.parse_clause()?;
+
+                    if pending_operator.take() == Some(Lexeme::And) && occur == Occur::Should {
+                        occur = Occur::Must;
+                    }
+
+                    clauses.push((occur, node));
+                }
+            }
+        }
+
+        if pending_operator.is_some() {
+            return Err(ParseError::UnexpectedEnd);
+        }
+
+        Ok(clauses)
+    }
+
+    fn parse_clause(&mut self) -> Result<(Occur, Node), ParseError> {
+        let occur = match self.peek() {
+            Some(Lexeme::Required) => {
+                self.next();
+                Occur::Must
+            }
+            Some(Lexeme::Excluded) => {
+                self.next();
+                Occur::MustNot
+            }
+            _ => Occur::Should,
+        };
+
+        let field = match self.peek() {
+            Some(Lexeme::Field(_)) => match self.next() {
+                Some(Lexeme::Field(field)) => Some(field),
+                _ => unreachable!(),
+            }
+            _ => None,
+        };
+
+        let mut node = match self.next() {
+            Some(Lexeme::Word(word)) => Node::Term(field, word),
+            Some(Lexeme::Prefix(prefix)) => Node::Prefix(field, prefix),
+            Some(Lexeme::Phrase(phrase)) => Node::Phrase(field, phrase),
+            Some(Lexeme::OpenGroup) => Node::Group(field, self.parse_clauses(true)?),
+            Some(lexeme) => return Err(ParseError::UnexpectedToken(lexeme.to_string())),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+
+        if let Some(Lexeme::Boost(boost)) = self.peek() {
+            node = Node::Boost(Box::new(node), *boost);
+            self.next();
+        }
+
+        Ok((occur, node))
+    }
+}
+
+struct QueryBuilder<'a> {
+    default_fields: &'a [(FieldId, f32)],
+    term_dict: &'a TermDictionary,
+    data_dict: &'a DataDictionary,
+}
+
+impl<'a> QueryBuilder<'a> {
+    fn resolve_fields(&self, field: &Option<String>, parent_fields: &[(FieldId, f32)]) -> Result<Vec<(FieldId, f32)>, ParseError> {
+        match field {
+            Some(name) => match self.data_dict.get_by_name(name) {
+                Some((field_id, _)) => Ok(vec![(field_id, 1.0)]),
+                None => Err(ParseError::UnknownField(name.clone())),
+            }
+            None => Ok(parent_fields.to_vec()),
+        }
+    }
+
+    // Builds a query for each field and combines them. Returns None if the text didn't produce any terms
+    fn build_per_field<F>(&self, fields: &[(FieldId, f32)], text: &str, build: F) -> Option<Query>
+        where F: Fn(FieldId, Vec<&str>) -> Query
+    {
+        let mut queries = Vec::new();
+
+        for (field_id, boost) in fields {
+            let analyzer = self.data_dict.get(*field_id).map(|field_config| field_config.analyzer).unwrap_or_default();
+            let tokens = analyzer.analyze(text);
+
+            if tokens.is_empty() {
+                continue;
+            }
+
+            let terms = tokens.iter().map(|token| token.term.as_str()).collect();
+            queries.push(Query::boost(build(*field_id, terms), *boost));
+        }
+
+        if queries.is_empty() {
+            None
+        } else {
+            Some(Query::or(queries))
+        }
+    }
+
+    fn build(&self, node: &Node, parent_fields: &[(FieldId, f32)]) -> Result<Option<Query>, ParseError> {
+        match node {
+            Node::Term(field, text) => {
+                let fields = self.resolve_fields(field, parent_fields)?;
+                Ok(self.build_per_field(&fields, text, |field_id, terms| {
+                    Query::or(terms.into_iter().filter_map(|term| self.term_dict.terms.get(term)).map(|term_id| Query::term(field_id, *term_id)).collect())
+                }))
+            }
+            Node::Phrase(field, text) => {
+                let fields = self.resolve_fields(field, parent_fields)?;
+                Ok(self.build_per_field(&fields, text, |field_id, terms| {
+                    match terms.into_iter().map(|term| self.term_dict.terms.get(term).cloned()).collect::<Option<Vec<_>>>() {
+                        Some(term_ids) => Query::phrase(field_id, term_ids),
+                        None => Query::match_none(),
+                    }
+                }))
+            }
+            Node::Prefix(field, text) => {
+                let fields = self.resolve_fields(field, parent_fields)?;
+                Ok(self.build_per_field(&fields, text, |field_id, terms| {
+                    // Only the last term is treated as a prefix, any before it must match exactly
+                    let mut terms = terms;
+                    let prefix = terms.pop().unwrap_or_default();
+                    let mut queries = terms.into_iter().map(|term| match self.term_dict.terms.get(term) {
+                        Some(term_id) => Query::term(field_id, *term_id),
+                        None => Query::match_none(),
+                    }).collect::<Vec<_>>();
+                    queries.push(Query::or(self.term_dict.terms_with_prefix(field_id, prefix).into_iter().take(MAX_PREFIX_EXPANSIONS).map(|term_id| Query::term(field_id, term_id)).collect()));
+                    Query::and(queries)
+                }))
+            }
+            Node::Group(field, clauses) => {
+                let fields = self.resolve_fields(field, parent_fields)?;
+                let mut must = Vec::new();
+                let mut should = Vec::new();
+                let mut must_not = Vec::new();
+
+                for (occur, node) in clauses {
+                    if let Some(query) = self.build(node, &fields)? {
+                        match occur {
+                            Occur::Must => must.push(query),
+                            Occur::Should => should.push(query),
+                            Occur::MustNot => must_not.push(query),
+                        }
+                    }
+                }
+
+                let query = if !must.is_empty() {
+                    let required = Query::and(must);
+
+                    if should.is_empty() {
+                        required
+                    } else {
+                        // Optional clauses don't affect which documents match, but they do contribute to the score
+                        should.insert(0, required.clone());
+                        Query::filter(Query::or(should), required)
+                    }
+                } else if !should.is_empty() {
+                    Query::or(should)
+                } else if !must_not.is_empty() {
+                    Query::match_all()
+                } else {
+                    return Ok(None);
+                };
+
+                if must_not.is_empty() {
+                    Ok(Some(query))
+                } else {
+                    Ok(Some(Query::exclude(query, Query::or(must_not))))
+                }
+            }
+            Node::Boost(node, boost) => {
+                Ok(self.build(node, parent_fields)?.map(|query| Query::boost(query, *boost)))
+            }
+        }
+    }
+}
+
+// Parses a user-facing query string. For example: title:"hello world" +summary:foo* -bar baz^2
+// Terms without a field prefix are searched for in all of the default fields
+pub fn parse(input: &str, default_fields: &[(FieldId, f32)], term_dict: &TermDictionary, data_dict: &DataDictionary) -> Result<Query, ParseError> {
+    let mut parser = Parser { lexemes: tokenize(input)?, position: 0 };
+    let clauses = parser.parse_clauses(false)?;
+
+    let builder = QueryBuilder { default_fields, term_dict, data_dict };
+    Ok(builder.build(&Node::Group(None, clauses), builder.default_fields)?.unwrap_or(Query::MatchNone))
+}
+
+// Like parse, but never fails. If the query string is malformed, all syntax is ignored and each word
+// is searched for in the default fields
+pub fn parse_simple(input: &str, default_fields: &[(FieldId, f32)], term_dict: &TermDictionary, data_dict: &DataDictionary) -> Query {
+    if let Ok(query) = parse(input, default_fields, term_dict, data_dict) {
+        return query;
+    }
+
+    let clauses = input.split(|c: char| c.is_whitespace() || "+-()\"^*:".contains(c))
+        .filter(|word| !word.is_empty() && *word != "AND" && *word != "OR")
+        .map(|word| (Occur::Should, Node::Term(None, word.to_owned())))
+        .collect();
+
+    let builder = QueryBuilder { default_fields, term_dict, data_dict };
+    builder.build(&Node::Group(None, clauses), builder.default_fields).ok().flatten().unwrap_or(Query::MatchNone)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::term_dictionary::TermDictionary;
+    use crate::data_dictionary::{FieldId, FieldConfig, FieldType, DataDictionary};
+    use crate::query::Query;
+    use super::{parse, parse_simple, ParseError};
+
+    fn setup() -> (TermDictionary, DataDictionary, FieldId, FieldId) {
+        let mut term_dict = TermDictionary::default();
+        let mut data_dict = DataDictionary::default();
+        let title = data_dict.insert("title".to_owned(), FieldConfig::default());
+        let body = data_dict.insert("body".to_owned(), FieldConfig::default());
+
+        for term in &["hello", "world", "help", "rust"] {
+            let term_id = term_dict.get_or_insert(term);
+            term_dict.add_to_field(title, term_id);
+            term_dict.add_to_field(body, term_id);
+        }
+
+        (term_dict, data_dict, title, body)
+    }
+
+    #[test]
+    fn test_terms() {
+        let (term_dict, data_dict, title, _) = setup();
+        let hello = term_dict.terms["hello"];
+        let world = term_dict.terms["world"];
+
+        assert_eq!(
+            parse("Hello world", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::Or(vec![Query::Term(title, hello), Query::Term(title, world)]))
+        );
+
+        // Unknown terms match nothing
+        assert_eq!(
+            parse("missing", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::MatchNone)
+        );
+    }
+
+    #[test]
+    fn test_fields_and_boosts() {
+        let (term_dict, data_dict, title, body) = setup();
+        let hello = term_dict.terms["hello"];
+        let rust = term_dict.terms["rust"];
+
+        assert_eq!(
+            parse("body:hello rust^2", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::Or(vec![Query::Term(body, hello), Query::Boost(Box::new(Query::Term(title, rust)), 2.0)]))
+        );
+
+        assert_eq!(
+            parse("missing:hello", &[(title, 1.0)], &term_dict, &data_dict),
+            Err(ParseError::UnknownField("missing".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_required_and_excluded() {
+        let (term_dict, data_dict, title, _) = setup();
+        let hello = term_dict.terms["hello"];
+        let world = term_dict.terms["world"];
+        let rust = term_dict.terms["rust"];
+
+        assert_eq!(
+            parse("+hello -world", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::Exclude(Box::new(Query::Term(title, hello)), Box::new(Query::Term(title, world))))
+        );
+
+        // Optional terms alongside required ones only affect the score
+        assert_eq!(
+            parse("+hello rust", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::Filter(
+                Box::new(Query::Or(vec![Query::Term(title, hello), Query::Term(title, rust)])),
+                Box::new(Query::Term(title, hello))
+            ))
+        );
+
+        assert_eq!(
+            parse("hello AND (world OR rust)", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::And(vec![Query::Term(title, hello), Query::Or(vec![Query::Term(title, world), Query::Term(title, rust)])]))
+        );
+    }
+
+    #[test]
+    fn test_phrase_and_prefix() {
+        let (term_dict, data_dict, title, _) = setup();
+        let hello = term_dict.terms["hello"];
+        let help = term_dict.terms["help"];
+        let world = term_dict.terms["world"];
+
+        assert_eq!(
+            parse("\"hello world\"", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::Phrase(title, vec![hello, world]))
+        );
+
+        assert_eq!(
+            parse("hel*", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::Or(vec![Query::Term(title, hello), Query::Term(title, help)]))
+        );
+    }
+
+    #[test]
+    fn test_prefix_only_expands_terms_in_field() {
+        let (mut term_dict, mut data_dict, title, _) = setup();
+        let hello = term_dict.terms["hello"];
+        let help = term_dict.terms["help"];
+        let tags = data_dict.insert("tags".to_owned(), FieldConfig::default().field_type(FieldType::Keyword));
+        let helmet = term_dict.get_or_insert("helmet");
+        term_dict.add_to_field(tags, helmet);
+
+        assert_eq!(
+            parse("hel*", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::Or(vec![Query::Term(title, hello), Query::Term(title, help)]))
+        );
+        assert_eq!(
+            parse("tags:hel*", &[(title, 1.0)], &term_dict, &data_dict),
+            Ok(Query::Term(tags, helmet))
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        let (term_dict, data_dict, title, _) = setup();
+        let hello = term_dict.terms["hello"];
+        let world = term_dict.terms["world"];
+
+        assert_eq!(parse("\"hello world", &[(title, 1.0)], &term_dict, &data_dict), Err(ParseError::UnclosedPhrase));
+        assert_eq!(parse("(hello world", &[(title, 1.0)], &term_dict, &data_dict), Err(ParseError::UnclosedGroup));
+        assert_eq!(parse("hello AND", &[(title, 1.0)], &term_dict, &data_dict), Err(ParseError::UnexpectedEnd));
+        assert_eq!(parse("hello^x", &[(title, 1.0)], &term_dict, &data_dict), Err(ParseError::InvalidBoost("x".to_owned())));
+
+        // Simple mode falls back to searching for each word
+        assert_eq!(
+            parse_simple("(hello \"world", &[(title, 1.0)], &term_dict, &data_dict),
+            Query::Or(vec![Query::Term(title, hello), Query::Term(title, world)])
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::cmp::Ordering;
+
+use super::data_dictionary::FieldId;
+use super::numeric_index::NumericValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+// Where to put documents that don't have a value for the field being sorted on
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingOrder {
+    First,
+    #[default]
+    Last,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Score,
+    Field(FieldId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub order: SortOrder,
+    pub missing: MissingOrder,
+}
+
+impl Sort {
+    pub fn score() -> Sort {
+        Sort { key: SortKey::Score, order: SortOrder::Desc, missing: MissingOrder::Last }
+    }
+
+    pub fn field(field: FieldId, order: SortOrder) -> Sort {
+        Sort { key: SortKey::Field(field), order, missing: MissingOrder::Last }
+    }
+}
+
+// A value read from a field's doc values
+// A field only ever contains one kind of value, but the same field can be an integer in one index and a float in
+// another when results from an alias are merged. So numbers are compared by value, and come before text
+#[derive(Debug, Clone)]
+pub enum SortValue<'a> {
+    Number(f64),
+    Integer(i64),
+    Text(&'a str),
+}
+
+// Floats are compared with total_cmp, so NaN scores can't break the sort
+impl<'a> Ord for SortValue<'a> {
+    fn cmp(&self, other: &SortValue<'a>) -> Ordering {
+        match (self, other) {
+            (SortValue::Number(a), SortValue::Number(b)) => a.total_cmp(b),
+            (SortValue::Integer(a), SortValue::Integer(b)) => a.cmp(b),
+            (SortValue::Integer(a), SortValue::Number(b)) => NumericValue::Integer(*a).cmp(&NumericValue::Float(*b)),
+            (SortValue::Number(a), SortValue::Integer(b)) => NumericValue::Float(*a).cmp(&NumericValue::Integer(*b)),
+            (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+            (SortValue::Text(_), _) => Ordering::Greater,
+            (_, SortValue::Text(_)) => Ordering::Less,
+        }
+    }
+}
+
+impl<'a> PartialOrd for SortValue<'a> {
+    fn partial_cmp(&self, other: &SortValue<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for SortValue<'a> {
+    fn eq(&self, other: &SortValue<'a>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for SortValue<'a> {}
+
+pub fn compare_values(a: &Option<SortValue>, b: &Option<SortValue>, sort: &Sort) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let ordering = a.cmp(b);
+
+            match sort.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        }
+        (Some(_), None) => if sort.missing == MissingOrder::Last { Ordering::Less } else { Ordering::Greater },
+        (None, Some(_)) => if sort.missing == MissingOrder::Last { Ordering::Greater } else { Ordering::Less },
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Compares the values of two documents for each sort in turn, until one of them differs
+pub fn compare_all(a: &[Option<SortValue>], b: &[Option<SortValue>], sort: &[Sort]) -> Ordering {
+    sort.iter().zip(a.iter().zip(b.iter()))
+        .map(|(sort, (a, b))| compare_values(a, b, sort))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::data_dictionary::FieldId;
+    use super::{Sort, SortOrder, SortValue, MissingOrder, compare_values, compare_all};
+
+    #[test]
+    fn test_compare_values() {
+        let asc = Sort::field(FieldId(1), SortOrder::Asc);
+        let desc = Sort::field(FieldId(1), SortOrder::Desc);
+
+        assert_eq!(compare_values(&Some(SortValue::Number(1.0)), &Some(SortValue::Number(2.0)), &asc), Ordering::Less);
+        assert_eq!(compare_values(&Some(SortValue::Number(1.0)), &Some(SortValue::Number(2.0)), &desc), Ordering::Greater);
+        assert_eq!(compare_values(&Some(SortValue::Text("apple")), &Some(SortValue::Text("banana")), &asc), Ordering::Less);
+        assert_eq!(compare_values(&Some(SortValue::Text("apple")), &Some(SortValue::Text("apple")), &desc), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_compare_integers_with_floats() {
+        let asc = Sort::field(FieldId(1), SortOrder::Asc);
+
+        // The same field can be an integer in one index and a float in another
+        assert_eq!(compare_values(&Some(SortValue::Integer(3)), &Some(SortValue::Number(2.5)), &asc), Ordering::Greater);
+        assert_eq!(compare_values(&Some(SortValue::Number(2.5)), &Some(SortValue::Integer(3)), &asc), Ordering::Less);
+        assert_eq!(compare_values(&Some(SortValue::Integer(3)), &Some(SortValue::Number(3.0)), &asc), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_sort_with_nan() {
+        let sort = [Sort::score()];
+        let mut values = vec![[Some(SortValue::Number(1.0))], [Some(SortValue::Number(f64::NAN))], [Some(SortValue::Number(3.0))], [Some(SortValue::Number(2.0))]];
+        for _ in 0..10 {
+            values.extend(values.clone());
+        }
+
+        // A NaN score must not stop the values from being sorted
+        values.sort_by(|a, b| compare_all(a, b, &sort));
+        let numbers = values.iter().filter_map(|value| match value[0] { Some(SortValue::Number(number)) if !number.is_nan() => Some(number), _ => None }).collect::<Vec<_>>();
+        assert!(numbers.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn test_compare_missing_values() {
+        let last = Sort::field(FieldId(1), SortOrder::Asc);
+        let first = Sort { missing: MissingOrder::First, ..last };
+
+        // Missing values go last by default, whichever order the values are sorted in
+        assert_eq!(compare_values(&Some(SortValue::Number(1.0)), &None, &last), Ordering::Less);
+        assert_eq!(compare_values(&Some(SortValue::Number(1.0)), &None, &Sort::field(FieldId(1), SortOrder::Desc)), Ordering::Less);
+        assert_eq!(compare_values(&None, &Some(SortValue::Number(1.0)), &last), Ordering::Greater);
+
+        assert_eq!(compare_values(&Some(SortValue::Number(1.0)), &None, &first), Ordering::Greater);
+        assert_eq!(compare_values(&None, &Some(SortValue::Number(1.0)), &first), Ordering::Less);
+        assert_eq!(compare_values(&None, &None, &first), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_compare_all() {
+        let sort = [Sort::field(FieldId(1), SortOrder::Desc), Sort::score()];
+
+        // The second sort is only used to break ties in the first
+        assert_eq!(compare_all(&[Some(SortValue::Number(2.0)), Some(SortValue::Number(0.1))], &[Some(SortValue::Number(1.0)), Some(SortValue::Number(0.9))], &sort), Ordering::Less);
+        assert_eq!(compare_all(&[Some(SortValue::Number(1.0)), Some(SortValue::Number(0.1))], &[Some(SortValue::Number(1.0)), Some(SortValue::Number(0.9))], &sort), Ordering::Greater);
+        assert_eq!(compare_all(&[Some(SortValue::Number(1.0)), None], &[Some(SortValue::Number(1.0)), None], &sort), Ordering::Equal);
+    }
+}
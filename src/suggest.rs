@@ -0,0 +1,257 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use fnv::FnvHashSet;
+
+use super::{Database, DocumentId};
+use super::term_dictionary::TermId;
+use super::data_dictionary::FieldId;
+use super::query::Query;
+
+// The number of corrections that are considered for each term when suggesting a phrase
+const MAX_CORRECTIONS_PER_TERM: usize = 3;
+
+// The number of combinations of corrections that are tried before giving up on suggesting a phrase
+const MAX_PHRASE_ATTEMPTS: usize = 64;
+
+// The number of single character insertions, deletions, substitutions or swaps of adjacent characters that turn one string into the other
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+
+    // distances[i][j] is the distance between the first i characters of a and the first j characters of b
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution_cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j - 1] + substitution_cost).min(distances[i - 1][j] + 1).min(distances[i][j - 1] + 1);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+// Short terms are allowed fewer edits, otherwise almost any other short term would be a correction of them
+fn max_edits(term: &str) -> usize {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermSuggestion {
+    pub term: TermId,
+    pub distance: usize,
+    pub document_frequency: usize,
+}
+
+// The number of documents that contain the term and haven't been deleted
+// Postings aren't removed when documents are deleted or replaced, so they have to be skipped here
+fn live_document_frequency(db: &Database, postings_list: &[(DocumentId, FnvHashSet<usize>, f32)]) -> usize {
+    postings_list.iter().filter(|(document_id, _, _)| !db.deleted_docs.contains(document_id)).count()
+}
+
+// Finds the terms in a field that are a few edits away from the given term
+// The closest terms are first. Terms with the same distance are ranked by the number of documents that contain them
+pub fn suggest_term(db: &Database, field: FieldId, term: &str) -> Vec<TermSuggestion> {
+    let index = match db.fields.get(&field) {
+        Some(index) => index,
+        None => return Vec::new(),
+    };
+
+    let max_edits = max_edits(term);
+    let term_length = term.chars().count();
+
+    // Only the terms that are in this field are candidates
+    let mut suggestions = index.postings.iter().filter_map(|(term_id, postings_list)| {
+        db.term_dictionary.term_ids.get(term_id).map(|candidate| (candidate, term_id, postings_list))
+    }).filter(|(candidate, _, _)| {
+        // Terms with very different lengths can't be close, so skip working out the distance for them
+        let candidate_length = candidate.chars().count();
+        *candidate != term && candidate_length + max_edits >= term_length && candidate_length <= term_length + max_edits
+    }).filter_map(|(candidate, term_id, postings_list)| {
+        let distance = edit_distance(term, candidate);
+        if distance > max_edits {
+            return None;
+        }
+
+        let document_frequency = live_document_frequency(db, postings_list);
+        if document_frequency == 0 {
+            return None;
+        }
+
+        Some((candidate, TermSuggestion { term: *term_id, distance, document_frequency }))
+    }).collect::<Vec<_>>();
+
+    suggestions.sort_by(|(a_term, a), (b_term, b)| {
+        a.distance.cmp(&b.distance).then_with(|| b.document_frequency.cmp(&a.document_frequency)).then_with(|| a_term.cmp(b_term))
+    });
+
+    suggestions.into_iter().map(|(_, suggestion)| suggestion).collect()
+}
+
+// Suggests a corrected version of the terms that matches at least one document when all of the terms are required
+// Combinations with the fewest edits are tried first. Returns None if the terms already match, or if no correction does
+pub fn suggest_phrase(db: &Database, field: FieldId, terms: &[&str]) -> Option<Vec<String>> {
+    let index = db.fields.get(&field)?;
+
+    // The candidates for each term along with their distance from it. Terms that are in the field are their own first candidate
+    let candidates = terms.iter().map(|term| {
+        let mut term_candidates = Vec::new();
+
+        if let Some((term_id, postings_list)) = db.term_dictionary.terms.get(*term).and_then(|term_id| index.postings.get(term_id).map(|postings_list| (term_id, postings_list))) {
+            if live_document_frequency(db, postings_list) > 0 {
+                term_candidates.push((*term_id, 0));
+            }
+        }
+
+        term_candidates.extend(suggest_term(db, field, term).into_iter().take(MAX_CORRECTIONS_PER_TERM).map(|suggestion| (suggestion.term, suggestion.distance)));
+        term_candidates
+    }).collect::<Vec<_>>();
+
+    if candidates.is_empty() || candidates.iter().any(|term_candidates| term_candidates.is_empty()) {
+        return None;
+    }
+
+    // Search through the combinations of candidates in order of their total distance
+    let cost = |choices: &Vec<usize>| choices.iter().zip(&candidates).map(|(choice, term_candidates)| term_candidates[*choice].1).sum::<usize>();
+    let first_choices = vec![0; candidates.len()];
+    let mut queue = BinaryHeap::new();
+    let mut seen = FnvHashSet::default();
+    queue.push(Reverse((cost(&first_choices), first_choices.clone())));
+    seen.insert(first_choices);
+
+    for _ in 0..MAX_PHRASE_ATTEMPTS {
+        let Reverse((total_distance, choices)) = queue.pop()?;
+        let term_ids = choices.iter().zip(&candidates).map(|(choice, term_candidates)| term_candidates[*choice].0).collect::<Vec<_>>();
+
+        if !db.simple_match(&Query::and(term_ids.iter().map(|term_id| Query::term(field, *term_id)).collect())).is_empty() {
+            if total_distance == 0 {
+                return None;
+            }
+
+            return term_ids.iter().map(|term_id| db.term_dictionary.term_ids.get(term_id).cloned()).collect();
+        }
+
+        for i in 0..choices.len() {
+            if choices[i] + 1 < candidates[i].len() {
+                let mut next_choices = choices.clone();
+                next_choices[i] += 1;
+
+                if seen.insert(next_choices.clone()) {
+                    queue.push(Reverse((cost(&next_choices), next_choices)));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Database, Document};
+    use crate::tsvector::TSVector;
+    use crate::data_dictionary::{FieldId, FieldConfig, FieldType};
+    use super::{edit_distance, suggest_term, suggest_phrase};
+
+    // Indexes each text into the field as a separate document
+    fn insert_texts(db: &mut Database, field: FieldId, texts: &[&str]) {
+        for text in texts {
+            let mut tsvector = TSVector::default();
+            for (position, word) in text.split_whitespace().enumerate() {
+                let term_info = tsvector.terms.entry(db.term_dictionary.get_or_insert(word)).or_default();
+                term_info.positions.push(position + 1);
+                term_info.weight += 1.0;
+                tsvector.length += 1;
+            }
+
+            let mut doc = Document::default();
+            doc.fields.insert(field, tsvector);
+            let pk = db.next_document_id.to_string();
+            db.insert_document(pk, doc);
+        }
+    }
+
+    fn suggested_terms(db: &Database, field: FieldId, term: &str) -> Vec<String> {
+        suggest_term(db, field, term).into_iter().map(|suggestion| db.term_dictionary.term_ids[&suggestion.term].clone()).collect()
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("kitten", "kitten"), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("café", "cafe"), 1);
+        assert_eq!(edit_distance("quikc", "quick"), 1);
+    }
+
+    #[test]
+    fn test_suggest_term() {
+        let mut db = Database::default();
+        let title = db.data_dictionary.insert("title".to_owned(), FieldConfig::default());
+        let body = db.data_dictionary.insert("body".to_owned(), FieldConfig::default());
+        let tags = db.data_dictionary.insert("tags".to_owned(), FieldConfig::default().field_type(FieldType::Keyword));
+        insert_texts(&mut db, title, &["quick brown fox", "quack", "quick", "quirk"]);
+        insert_texts(&mut db, body, &["quock"]);
+
+        // Keyword values share the term dictionary, but aren't in the field
+        let quirky = db.term_dictionary.get_or_insert("quirky");
+        let mut doc = Document::default();
+        doc.keyword_fields.insert(tags, vec![quirky]);
+        db.insert_document("tagged".to_owned(), doc);
+
+        assert_eq!(suggested_terms(&db, title, "quikc"), vec!["quick"]);
+
+        // "quick" and "quirk" are both one edit away, but "quick" is in more documents
+        // "quock" and "quirky" are close too, but they are in other fields
+        assert_eq!(suggested_terms(&db, title, "quirck"), vec!["quick", "quirk", "quack"]);
+        assert_eq!(suggested_terms(&db, body, "quirck"), vec!["quock"]);
+
+        // Short terms are never corrected
+        assert_eq!(suggest_term(&db, title, "fx"), vec![]);
+
+        // Deleted documents aren't counted, so "quirk" is now in more documents than "quick"
+        db.delete_document_by_pk("2");
+        insert_texts(&mut db, title, &["quirk"]);
+        assert_eq!(suggested_terms(&db, title, "quirck"), vec!["quirk", "quick", "quack"]);
+
+        // Terms that are only in deleted documents aren't suggested
+        db.delete_document_by_pk("0");
+        assert_eq!(suggested_terms(&db, title, "quirck"), vec!["quirk", "quack"]);
+    }
+
+    #[test]
+    fn test_suggest_phrase() {
+        let mut db = Database::default();
+        let field = db.data_dictionary.insert("title".to_owned(), FieldConfig::default());
+        insert_texts(&mut db, field, &["quick brown fox", "quack brown dog"]);
+
+        assert_eq!(suggest_phrase(&db, field, &["quikc", "brwn", "fox"]), Some(vec!["quick".to_owned(), "brown".to_owned(), "fox".to_owned()]));
+
+        // "quack" is as close as "quick" but only "quick" is in a document with "fox"
+        assert_eq!(suggest_phrase(&db, field, &["quuck", "fox"]), Some(vec!["quick".to_owned(), "fox".to_owned()]));
+
+        // Nothing to correct
+        assert_eq!(suggest_phrase(&db, field, &["quick", "fox"]), None);
+
+        // No correction matches anything
+        assert_eq!(suggest_phrase(&db, field, &["fox", "dog"]), None);
+        assert_eq!(suggest_phrase(&db, field, &["xyzzy"]), None);
+    }
+}
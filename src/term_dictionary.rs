@@ -1,15 +1,21 @@
 use std::collections::hash_map::HashMap;
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
+
+use super::data_dictionary::FieldId;
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
 #[serde(transparent)]
 pub struct TermId(pub u32);
 
-#[derive(Debug, Default, serde_derive::Serialize)]
+#[derive(Debug, Default, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct TermDictionary {
     next_id: u32,
     pub terms: HashMap<String, TermId>,
     pub term_ids: FnvHashMap<TermId, String>,
+
+    // The terms that have been indexed into each field. All fields share the dictionary, so this is needed to tell
+    // the terms of a keyword field apart from the words of a text field
+    pub field_terms: FnvHashMap<FieldId, FnvHashSet<TermId>>,
 }
 
 impl TermDictionary {
@@ -24,4 +30,32 @@ impl TermDictionary {
             id
         }
     }
+
+    // Records that the term has been indexed into the field
+    pub fn add_to_field(&mut self, field: FieldId, term: TermId) {
+        self.field_terms.entry(field).or_default().insert(term);
+    }
+
+    // Finds a term, but only if it has been indexed into the given field
+    pub fn get_in_field(&self, field: FieldId, term: &str) -> Option<TermId> {
+        let term_id = *self.terms.get(term)?;
+
+        if self.field_terms.get(&field)?.contains(&term_id) {
+            Some(term_id)
+        } else {
+            None
+        }
+    }
+
+    // Returns the ids of the terms in the field that start with the given prefix, in alphabetical order
+    pub fn terms_with_prefix(&self, field: FieldId, prefix: &str) -> Vec<TermId> {
+        let field_terms = match self.field_terms.get(&field) {
+            Some(field_terms) => field_terms,
+            None => return Vec::new(),
+        };
+
+        let mut terms = field_terms.iter().filter_map(|term_id| self.term_ids.get(term_id).map(|term| (term, *term_id))).filter(|(term, _)| term.starts_with(prefix)).collect::<Vec<_>>();
+        terms.sort_by_key(|(term, _)| *term);
+        terms.into_iter().map(|(_, term_id)| term_id).collect()
+    }
 }
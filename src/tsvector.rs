@@ -3,7 +3,7 @@ use fnv::FnvHashMap;
 
 use super::TermId;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct TSVectorTerm {
     pub positions: Vec<usize>,
     pub weight: f32,
@@ -18,7 +18,7 @@ impl Default for TSVectorTerm {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct TSVector {
     pub length: usize,
     pub terms: FnvHashMap<TermId, TSVectorTerm>,